@@ -0,0 +1,400 @@
+use crate::instruction::Instruction;
+use crate::json::Json;
+use crate::keccak::keccak256;
+use crate::opcode::Opcode;
+use crate::uint::U256;
+use std::fmt;
+use std::str::FromStr;
+
+/// A static Solidity ABI type, encoded as a single 32-byte word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbiType {
+  /// `uintN`, with `N` a multiple of 8 up to 256.
+  Uint(usize),
+  /// `intN`, with `N` a multiple of 8 up to 256.
+  Int(usize),
+  Address,
+  Bool,
+  /// `bytesN`, with `N` from 1 to 32.
+  FixedBytes(usize),
+}
+
+impl AbiType {
+  /// Instructions that clean the word on top of the stack into a valid value
+  /// of this type: unused bits are cleared, or copies of the sign bit for
+  /// signed integers, and booleans become 0 or 1.
+  pub fn cleanup(&self) -> Vec<Instruction> {
+    match *self {
+      AbiType::Uint(256) | AbiType::Int(256) | AbiType::FixedBytes(32) => vec![],
+      AbiType::Uint(bits) => mask(U256::MAX >> (256 - bits)),
+      AbiType::Address => mask(U256::MAX >> 96),
+      AbiType::Int(bits) => vec![
+        Instruction::Push(U256::from(bits / 8 - 1)),
+        Instruction::Op(Opcode::SIGNEXTEND),
+      ],
+      AbiType::Bool => vec![
+        Instruction::Op(Opcode::ISZERO),
+        Instruction::Op(Opcode::ISZERO),
+      ],
+      AbiType::FixedBytes(size) => mask(U256::MAX << (256 - 8 * size)),
+    }
+  }
+}
+
+fn mask(mask: U256) -> Vec<Instruction> {
+  vec![Instruction::Push(mask), Instruction::Op(Opcode::AND)]
+}
+
+impl FromStr for AbiType {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let size = |digits: &str, default: usize| match digits {
+      "" => Some(default),
+      _ if digits.starts_with('0') => None,
+      _ => digits.parse::<usize>().ok(),
+    };
+
+    let ty = match s {
+      "address" => Some(AbiType::Address),
+      "bool" => Some(AbiType::Bool),
+      _ if s.starts_with("uint") => size(&s[4..], 256)
+        .filter(|bits| *bits > 0 && bits % 8 == 0 && *bits <= 256)
+        .map(AbiType::Uint),
+      _ if s.starts_with("int") => size(&s[3..], 256)
+        .filter(|bits| *bits > 0 && bits % 8 == 0 && *bits <= 256)
+        .map(AbiType::Int),
+      _ if s.starts_with("bytes") && s.len() > 5 => size(&s[5..], 0)
+        .filter(|size| (1..=32).contains(size))
+        .map(AbiType::FixedBytes),
+      _ => None,
+    };
+
+    ty.ok_or_else(|| match s {
+      "" => "Expected a type".to_owned(),
+      "bytes" | "string" => format!("`{}` is dynamic; only static types are supported", s),
+      _ if s.ends_with(']') || s.starts_with('(') => {
+        format!("`{}` is not supported; only elementary static types are", s)
+      }
+      _ => format!("Unknown type `{}`", s),
+    })
+  }
+}
+
+impl fmt::Display for AbiType {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      AbiType::Uint(bits) => write!(f, "uint{}", bits),
+      AbiType::Int(bits) => write!(f, "int{}", bits),
+      AbiType::Address => write!(f, "address"),
+      AbiType::Bool => write!(f, "bool"),
+      AbiType::FixedBytes(size) => write!(f, "bytes{}", size),
+    }
+  }
+}
+
+/// A parameter or return value, optionally named.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+  pub ty: AbiType,
+  pub name: String,
+}
+
+/// Whether a function reads or changes state, and whether it accepts ether,
+/// as declared in its signature. It is listed in the ABI but not enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mutability {
+  Pure,
+  View,
+  #[default]
+  NonPayable,
+  Payable,
+}
+
+impl Mutability {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Mutability::Pure => "pure",
+      Mutability::View => "view",
+      Mutability::NonPayable => "nonpayable",
+      Mutability::Payable => "payable",
+    }
+  }
+}
+
+impl FromStr for Mutability {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "pure" => Ok(Mutability::Pure),
+      "view" => Ok(Mutability::View),
+      "nonpayable" => Ok(Mutability::NonPayable),
+      "payable" => Ok(Mutability::Payable),
+      _ => Err(format!("Unknown state mutability `{}`", s)),
+    }
+  }
+}
+
+/// A function signature such as `transfer(address to, uint256 amount)`,
+/// optionally followed by a state mutability and `returns (...)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+  pub name: String,
+  pub inputs: Vec<Param>,
+  pub outputs: Vec<Param>,
+  pub mutability: Mutability,
+}
+
+impl Signature {
+  /// The form that is hashed, e.g. `transfer(address,uint256)`.
+  pub fn canonical(&self) -> String {
+    let types = self
+      .inputs
+      .iter()
+      .map(|param| param.ty.to_string())
+      .collect::<Vec<String>>();
+
+    format!("{}({})", self.name, types.join(","))
+  }
+
+  /// The first four bytes of the Keccak-256 hash of the canonical form.
+  pub fn selector(&self) -> [u8; 4] {
+    let hash = keccak256(self.canonical().as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+  }
+}
+
+impl FromStr for Signature {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (name, inputs, mut rest) = split_signature(s, "function")?;
+    let mut mutability = Mutability::default();
+
+    if let Some(word) = rest.split(|c: char| c.is_whitespace() || c == '(').next() {
+      if let Ok(parsed) = word.parse() {
+        mutability = parsed;
+        rest = rest[word.len()..].trim();
+      }
+    }
+
+    let outputs = match rest.strip_prefix("returns") {
+      Some(outputs) => outputs
+        .trim()
+        .strip_prefix('(')
+        .and_then(|outputs| outputs.strip_suffix(')'))
+        .ok_or_else(|| "Expected `returns (...)`".to_owned())?,
+      None if rest.is_empty() => "",
+      None => return Err(format!("Unexpected `{}` after the parameters", rest)),
+    };
+
+    Ok(Signature {
+      name: name.to_owned(),
+      inputs: parse_params(inputs)?,
+      outputs: parse_params(outputs)?,
+      mutability,
+    })
+  }
+}
+
+/// An event parameter. Indexed parameters become topics, the others are
+/// ABI-encoded into the log data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventParam {
+  pub ty: AbiType,
+  pub name: String,
+  pub indexed: bool,
+}
+
+/// An event signature such as `Transfer(address indexed from, address
+/// indexed to, uint256 amount)`, optionally followed by `anonymous`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+  pub name: String,
+  pub inputs: Vec<EventParam>,
+  /// Anonymous events leave out the signature topic, leaving room for a
+  /// fourth indexed parameter.
+  pub anonymous: bool,
+}
+
+impl Event {
+  /// The form that is hashed, e.g. `Transfer(address,address,uint256)`.
+  pub fn canonical(&self) -> String {
+    let types = self
+      .inputs
+      .iter()
+      .map(|param| param.ty.to_string())
+      .collect::<Vec<String>>();
+
+    format!("{}({})", self.name, types.join(","))
+  }
+
+  /// The first topic of every log of a non-anonymous event: the Keccak-256
+  /// hash of the canonical form.
+  pub fn topic(&self) -> [u8; 32] {
+    keccak256(self.canonical().as_bytes())
+  }
+}
+
+impl FromStr for Event {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (name, inputs, rest) = split_signature(s, "event")?;
+
+    let anonymous = match rest {
+      "" => false,
+      "anonymous" => true,
+      _ => return Err(format!("Unexpected `{}` after the parameters", rest)),
+    };
+
+    let inputs = parse_list(inputs, |ty, words| {
+      let indexed = words.first() == Some(&"indexed");
+      let name = words.get(indexed as usize).unwrap_or(&"").to_string();
+
+      match words.get(indexed as usize + 1) {
+        Some(extra) => Err(format!("Unexpected `{}`", extra)),
+        None => Ok(EventParam { ty, name, indexed }),
+      }
+    })?;
+
+    let limit = if anonymous { 4 } else { 3 };
+    if inputs.iter().filter(|param| param.indexed).count() > limit {
+      return Err(format!("Events can have at most {} indexed parameters", limit));
+    }
+
+    Ok(Event {
+      name: name.to_owned(),
+      inputs,
+      anonymous,
+    })
+  }
+}
+
+/// Splits `name(params) rest` into its three parts, trimmed, checking that
+/// the name is a valid identifier.
+fn split_signature<'a>(s: &'a str, kind: &str) -> Result<(&'a str, &'a str, &'a str), String> {
+  let (name, rest) = s
+    .trim()
+    .split_once('(')
+    .ok_or_else(|| format!("Expected `(` after the {} name", kind))?;
+  let (params, rest) = rest
+    .split_once(')')
+    .ok_or_else(|| "Expected `)` after the parameters".to_owned())?;
+
+  let name = name.trim();
+  let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+  if !valid_name {
+    return Err(format!("Invalid {} name `{}`", kind, name));
+  }
+
+  Ok((name, params, rest.trim()))
+}
+
+/// Something a contract exposes to callers, as listed in its ABI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+  Function(Signature),
+  /// Code that runs when no function matches.
+  Fallback,
+  Event(Event),
+}
+
+/// The Solidity ABI JSON description of a contract exposing `entries`.
+pub fn describe(entries: &[Entry]) -> Json {
+  let params = |params: &[Param]| {
+    params
+      .iter()
+      .map(|param| {
+        Json::object(vec![
+          ("name", Json::str(param.name.as_str())),
+          ("type", Json::str(param.ty.to_string())),
+          ("internalType", Json::str(param.ty.to_string())),
+        ])
+      })
+      .collect()
+  };
+
+  let entries = entries
+    .iter()
+    .map(|entry| match entry {
+      Entry::Function(signature) => Json::object(vec![
+        ("type", Json::str("function")),
+        ("name", Json::str(signature.name.as_str())),
+        ("inputs", Json::Array(params(&signature.inputs))),
+        ("outputs", Json::Array(params(&signature.outputs))),
+        ("stateMutability", Json::str(signature.mutability.name())),
+      ]),
+      Entry::Fallback => Json::object(vec![
+        ("type", Json::str("fallback")),
+        ("stateMutability", Json::str(Mutability::NonPayable.name())),
+      ]),
+      Entry::Event(event) => Json::object(vec![
+        ("type", Json::str("event")),
+        ("name", Json::str(event.name.as_str())),
+        (
+          "inputs",
+          Json::Array(
+            event
+              .inputs
+              .iter()
+              .map(|param| {
+                Json::object(vec![
+                  ("name", Json::str(param.name.as_str())),
+                  ("type", Json::str(param.ty.to_string())),
+                  ("internalType", Json::str(param.ty.to_string())),
+                  ("indexed", Json::Bool(param.indexed)),
+                ])
+              })
+              .collect(),
+          ),
+        ),
+        ("anonymous", Json::Bool(event.anonymous)),
+      ]),
+    })
+    .collect();
+
+  Json::Array(entries)
+}
+
+/// Parses a comma separated list of types, each optionally followed by a
+/// name.
+fn parse_params(list: &str) -> Result<Vec<Param>, String> {
+  parse_list(list, |ty, words| match words {
+    [] | [_] => Ok(Param {
+      ty,
+      name: words.first().unwrap_or(&"").to_string(),
+    }),
+    [_, extra, ..] => Err(format!("Unexpected `{}`", extra)),
+  })
+}
+
+/// Parses a comma separated list of parameters, each a type followed by the
+/// words `param` makes sense of.
+fn parse_list<T>(
+  list: &str,
+  param: impl Fn(AbiType, &[&str]) -> Result<T, String>,
+) -> Result<Vec<T>, String> {
+  if list.trim().is_empty() {
+    return Ok(vec![]);
+  }
+
+  list
+    .split(',')
+    .map(|text| {
+      let words = text.split_whitespace().collect::<Vec<&str>>();
+      let ty = words.first().unwrap_or(&"").parse()?;
+
+      param(ty, words.get(1..).unwrap_or(&[]))
+        .map_err(|message| format!("{} in parameter `{}`", message, text.trim()))
+    })
+    .collect()
+}
+
+/// Parses a comma separated list of types, as in `address,uint256`.
+pub fn parse_types(list: &str) -> Result<Vec<AbiType>, String> {
+  Ok(parse_params(list)?.into_iter().map(|param| param.ty).collect())
+}
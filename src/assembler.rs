@@ -0,0 +1,99 @@
+use crate::instruction::{Instruction, Label};
+use crate::opcode::Opcode;
+use crate::uint::U256;
+use std::collections::HashMap;
+
+/// The result of assembling a list of instructions.
+pub struct Assembly {
+  pub byte_code: Vec<u8>,
+  /// The byte offset at which each instruction starts.
+  pub offsets: Vec<usize>,
+}
+
+/// Lays out `code` and resolves every label reference to the byte offset of
+/// its JUMPDEST or data.
+///
+/// Each label reference uses the narrowest PUSH that fits its target. Since
+/// widening one reference can move every label after it, layout is repeated
+/// until no reference needs to grow. Widths only ever increase, so this
+/// terminates.
+pub fn assemble(code: &[Instruction]) -> Assembly {
+  let mut widths = vec![1; code.len()];
+
+  let (offsets, labels) = loop {
+    let (offsets, labels) = layout(code, &widths);
+    let mut stable = true;
+
+    for (i, instruction) in code.iter().enumerate() {
+      if let Instruction::PushLabel(label) = instruction {
+        let needed = push_data(labels[label]).len();
+
+        if needed > widths[i] {
+          widths[i] = needed;
+          stable = false;
+        }
+      }
+    }
+
+    if stable {
+      break (offsets, labels);
+    }
+  };
+
+  let mut byte_code = Vec::new();
+
+  for (i, instruction) in code.iter().enumerate() {
+    match instruction {
+      Instruction::Op(opcode) => byte_code.push(opcode.0),
+      Instruction::Push(value) => push(&mut byte_code, &value.to_be_bytes_trimmed()),
+      Instruction::Label(_) => byte_code.push(Opcode::JUMPDEST.0),
+      Instruction::PushLabel(label) => {
+        let target = U256::from(labels[label] as u64).to_be_bytes();
+        push(&mut byte_code, &target[32 - widths[i]..]);
+      }
+      Instruction::Raw(bytes) | Instruction::Data(_, bytes) => {
+        byte_code.extend_from_slice(bytes)
+      }
+    }
+  }
+
+  Assembly { byte_code, offsets }
+}
+
+/// Computes the offset of every instruction and label, given the current
+/// width of each label reference.
+fn layout(code: &[Instruction], widths: &[usize]) -> (Vec<usize>, HashMap<Label, usize>) {
+  let mut offsets = Vec::with_capacity(code.len());
+  let mut labels = HashMap::new();
+  let mut offset = 0;
+
+  for (i, instruction) in code.iter().enumerate() {
+    offsets.push(offset);
+
+    offset += match instruction {
+      Instruction::Op(_) => 1,
+      Instruction::Push(value) => 1 + value.to_be_bytes_trimmed().len(),
+      Instruction::Label(label) => {
+        labels.insert(*label, offset);
+        1
+      }
+      Instruction::PushLabel(_) => 1 + widths[i],
+      Instruction::Raw(bytes) => bytes.len(),
+      Instruction::Data(label, bytes) => {
+        labels.insert(*label, offset);
+        bytes.len()
+      }
+    };
+  }
+
+  (offsets, labels)
+}
+
+fn push_data(offset: usize) -> Vec<u8> {
+  U256::from(offset as u64).to_be_bytes_trimmed()
+}
+
+fn push(byte_code: &mut Vec<u8>, data: &[u8]) {
+  byte_code.push(Opcode::push(data.len()).0);
+  byte_code.extend_from_slice(data);
+}
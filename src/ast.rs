@@ -1,32 +1,40 @@
+use crate::abi::{AbiType, Event, Signature};
+use crate::diagnostic::Span;
+use crate::instruction::Instruction;
+use crate::opcode::Opcode;
+use crate::uint::U256;
+
 #[derive(Debug, Clone)]
 pub struct Expression {
   pub op: Op,
   pub exprs: Vec<Expression>,
+  pub span: Span,
 }
 
 impl Expression {
+  pub fn new(op: Op, exprs: Vec<Expression>, span: Span) -> Self {
+    Self { op, exprs, span }
+  }
+
   pub fn new_program() -> Self {
     Self {
       op: Op::Start,
       exprs: vec![],
-    }
-  }
-
-  pub fn end_program() -> Self {
-    Self {
-      op: Op::End,
-      exprs: vec![]
+      span: Span::default(),
     }
   }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum Op {
   Add,
   Mul,
   Div,
+  SDiv,
   Sub,
   Mod,
+  SMod,
+  SignExtend,
   And,
   Or,
   XOr,
@@ -35,12 +43,64 @@ pub enum Op {
   LtOE,
   Gt,
   GtOE,
+  SLt,
+  SLtOE,
+  SGt,
+  SGtOE,
   Eq,
   NotEq,
+  MLoad,
+  MStore,
+  SLoad,
+  SStore,
   If,
   When,
   Unless,
+  While,
+  Until,
+  For,
   Start,
-  End,
-  Num(u32),
+  /// `(seq ...)` or `{ ... }`: runs each expression in order, keeping only
+  /// the value of the last.
+  Seq,
+  Num(U256),
+  Def(String, Vec<String>),
+  Ident(String),
+  /// `(let ((name value)...) body)`. The expressions are the bound values in
+  /// order, followed by the body.
+  Let(Vec<(String, Span)>),
+  /// Inline assembly, emitted as is.
+  Asm(Vec<Instruction>),
+  /// A builtin such as `caller` or `(calldataload 4)`, which compiles to a
+  /// single opcode taking the expressions as its inputs.
+  Builtin(Opcode),
+  /// `(returnlll runtime)`: init code that returns `runtime`, compiled as a
+  /// separate program, as the code of the contract.
+  ReturnLll,
+  /// `(deploy init runtime)`: like `returnlll`, but runs `init` first.
+  Deploy,
+  /// `(dispatch ("signature" handler)... fallback)`. The expressions are the
+  /// handlers in order, followed by the fallback if there is one.
+  Dispatch(Vec<(Signature, Span)>),
+  /// `(abi-encode "types" offset values...)`. The expressions are the offset
+  /// followed by the values.
+  AbiEncode(Vec<AbiType>),
+  /// `(abi-decode "type" index)`: argument `index` of the call.
+  AbiDecode(AbiType, usize),
+  /// `(emit "event" values...)`: logs the event, with one expression per
+  /// parameter.
+  Emit(Event),
+  /// `(return offset size)`, or `(return value)` to return a single word.
+  Return,
+  /// `(require cond "message")`: reverts unless `cond` holds, with the
+  /// message as a Solidity `Error(string)` if there is one.
+  Require(Option<String>),
+  /// `(and ...)`: 1 if every expression is non-zero, else 0. Evaluation
+  /// stops at the first zero. Unlike `&`, this is not bitwise.
+  LogicalAnd,
+  /// `(or ...)`: 1 if any expression is non-zero, else 0. Evaluation stops
+  /// at the first non-zero.
+  LogicalOr,
+  /// `(not x)`: 1 if `x` is zero, else 0. Unlike `~`, this is not bitwise.
+  LogicalNot,
 }
@@ -0,0 +1,62 @@
+use crate::opcode::Opcode;
+
+/// Names that compile to the opcode of the same name. Their arguments are
+/// the opcode's inputs, with the first argument on top of the stack.
+const BUILTINS: [&str; 42] = [
+  // Execution environment.
+  "address",
+  "balance",
+  "origin",
+  "caller",
+  "callvalue",
+  "calldataload",
+  "calldatasize",
+  "calldatacopy",
+  "codesize",
+  "codecopy",
+  "gasprice",
+  "extcodesize",
+  "extcodecopy",
+  "returndatasize",
+  "returndatacopy",
+  "extcodehash",
+  "selfbalance",
+  "gas",
+  "msize",
+  "keccak256",
+  // Block context.
+  "blockhash",
+  "coinbase",
+  "timestamp",
+  "number",
+  "prevrandao",
+  "gaslimit",
+  "chainid",
+  "basefee",
+  "blobhash",
+  "blobbasefee",
+  // Calls and contract creation.
+  "call",
+  "staticcall",
+  "delegatecall",
+  "create",
+  "create2",
+  // Logging.
+  "log0",
+  "log1",
+  "log2",
+  "log3",
+  "log4",
+  // Halting. `return` also has a one-argument form, so it is not a builtin.
+  "stop",
+  "revert",
+];
+
+/// The opcode `name` compiles to, if it is a builtin.
+pub fn builtin(name: &str) -> Option<Opcode> {
+  if BUILTINS.contains(&name) {
+    Opcode::from_name(name)
+  } else {
+    None
+  }
+}
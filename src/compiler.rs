@@ -1,262 +1,1392 @@
+use crate::abi::{AbiType, Entry, Event, Signature};
+use crate::assembler::assemble;
 use crate::ast::{Expression, Op};
+use crate::diagnostic::{Diagnostic, Span};
+use crate::instruction::{Instruction, Label};
+use crate::keccak::keccak256;
+use crate::opcode::Opcode;
+use crate::optimizer::{optimize, optimize_annotated, OptLevel};
+use crate::output::to_hex;
+use crate::stack::{heights, net_effect, StackError};
+use crate::uint::U256;
+use std::collections::HashMap;
+
+/// Generated instructions, each paired with the span of the innermost
+/// expression that produced it once that expression has been compiled.
+#[derive(Debug, Clone, Default)]
+struct Code {
+  instructions: Vec<Instruction>,
+  spans: Vec<Option<Span>>,
+}
+
+impl Code {
+  fn push(&mut self, instruction: Instruction) {
+    self.instructions.push(instruction);
+    self.spans.push(None);
+  }
+
+  /// Attributes every instruction that has no span yet to `span`.
+  fn claim(&mut self, span: Span) {
+    for slot in self.spans.iter_mut().filter(|slot| slot.is_none()) {
+      *slot = Some(span);
+    }
+  }
+}
+
+impl From<Vec<Instruction>> for Code {
+  fn from(instructions: Vec<Instruction>) -> Self {
+    let spans = vec![None; instructions.len()];
+    Code {
+      instructions,
+      spans,
+    }
+  }
+}
+
+impl Extend<Instruction> for Code {
+  fn extend<I: IntoIterator<Item = Instruction>>(&mut self, iter: I) {
+    for instruction in iter {
+      self.push(instruction);
+    }
+  }
+}
+
+impl Extend<(Instruction, Option<Span>)> for Code {
+  fn extend<I: IntoIterator<Item = (Instruction, Option<Span>)>>(&mut self, iter: I) {
+    for (instruction, span) in iter {
+      self.instructions.push(instruction);
+      self.spans.push(span);
+    }
+  }
+}
+
+impl IntoIterator for Code {
+  type Item = (Instruction, Option<Span>);
+  type IntoIter =
+    std::iter::Zip<std::vec::IntoIter<Instruction>, std::vec::IntoIter<Option<Span>>>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.instructions.into_iter().zip(self.spans)
+  }
+}
+
+/// Local variables live in consecutive 32-byte memory slots from here up.
+/// Lower memory is left to the program.
+const LOCALS_BASE: u64 = 0x80;
+
+/// Maximum nesting of macro expansions before we assume a definition is
+/// recursive.
+const MAX_EXPANSION_DEPTH: usize = 256;
+
+struct Definition {
+  params: Vec<String>,
+  body: Expression,
+  /// Names the body uses without binding them, and where.
+  free: Vec<(String, Span)>,
+  span: Span,
+  used: bool,
+}
+
+/// A `let` binding that is in scope.
+struct Variable {
+  name: String,
+  span: Span,
+  slot: U256,
+}
+
+/// The range of generated bytes `offset..offset + length` that came from the
+/// expression at `span`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceMapping {
+  pub offset: usize,
+  pub length: usize,
+  pub span: Span,
+}
 
 pub struct Compiler {
   ast: Expression,
-  pc: usize,
+  labels: usize,
+  definitions: HashMap<String, Definition>,
+  depth: usize,
+  /// Macro expansions so far, numbering the binders each one renames.
+  expansions: usize,
+  /// Whether the next expression is a statement, whose value, if any, is
+  /// not used. Only statements can be definitions.
+  statement: bool,
+  scope: Vec<Variable>,
+  warnings: Vec<Diagnostic>,
+  source_map: Vec<SourceMapping>,
+  spans: Vec<Span>,
+  interface: Vec<Entry>,
+  level: OptLevel,
 }
 
 impl Compiler {
   pub fn new(ast: Expression) -> Self {
-    Self { ast, pc: 0 }
+    Self {
+      ast,
+      labels: 0,
+      definitions: HashMap::new(),
+      depth: 0,
+      expansions: 0,
+      statement: false,
+      scope: Vec::new(),
+      warnings: Vec::new(),
+      source_map: Vec::new(),
+      spans: Vec::new(),
+      interface: Vec::new(),
+      level: OptLevel::default(),
+    }
+  }
+
+  /// Sets how much the generated code is optimized. Defaults to `O0`.
+  pub fn with_optimization(mut self, level: OptLevel) -> Self {
+    self.level = level;
+    self
+  }
+
+  /// Warnings collected by the last call to `compile`.
+  pub fn warnings(&self) -> &[Diagnostic] {
+    &self.warnings
+  }
+
+  /// Maps the bytes of each generated instruction to the innermost expression
+  /// that produced it, in order of offset, as of the last call to `compile`.
+  pub fn source_map(&self) -> &[SourceMapping] {
+    &self.source_map
   }
 
-  pub fn compile(&mut self) -> Result<String, String> {
-    let mut byte_code: Vec<String> = Vec::new();
+  /// The functions and fallbacks of every `dispatch`, and the events of
+  /// every `emit`, compiled by the last call to `compile`, in order of
+  /// appearance.
+  pub fn interface(&self) -> &[Entry] {
+    &self.interface
+  }
+
+  pub fn compile(&mut self) -> Result<Vec<u8>, Diagnostic> {
+    let code = self.generate()?;
+    let assembly = assemble(&code);
+
+    let offset_of = |index: usize| {
+      assembly
+        .offsets
+        .get(index)
+        .copied()
+        .unwrap_or(assembly.byte_code.len())
+    };
+
+    self.source_map = self
+      .spans
+      .iter()
+      .enumerate()
+      .map(|(i, span)| SourceMapping {
+        offset: offset_of(i),
+        length: offset_of(i + 1) - offset_of(i),
+        span: *span,
+      })
+      .filter(|mapping| mapping.length > 0)
+      .collect();
+
+    Ok(assembly.byte_code)
+  }
+
+  /// Generates the instruction stream for the program without assembling it.
+  pub fn generate(&mut self) -> Result<Vec<Instruction>, Diagnostic> {
+    let mut code = Vec::new();
+    self.spans.clear();
+    self.interface.clear();
+    self.warnings.clear();
+    self.scope.clear();
+    self.definitions.clear();
+    self.expansions = 0;
+    self.depth = 0;
+
+    // The stack height between top-level expressions, while it is known.
+    let mut height = Some(0);
+
     for expression in self.ast.exprs.clone().into_iter() {
-      byte_code = vec![byte_code, self.compile_expression(&expression)?].concat();
+      self.statement = true;
+      let generated = self.compile_expression(&expression)?;
+      let (generated, spans) =
+        optimize_annotated(generated.instructions, generated.spans, self.level);
+
+      if let Some(start_height) = height {
+        height = check_stack(&generated, start_height, &expression)?;
+      }
+
+      code.extend(generated);
+      self.spans.extend(spans.into_iter().map(|span| span.unwrap_or(expression.span)));
     }
 
-    Ok(
-      byte_code
-        .clone()
-        .into_iter()
-        .map(|x: String| {
-          if x.starts_with("jump-") {
-            let split = x.split("-");
-            let num = split.last().expect("Malformed jump");
-
-            let byte_dest = {
-              byte_code
-                .clone()
-                .into_iter()
-                .position(|s| {
-                  return s == format!("dest-{}", num);
-                })
-                .expect("No destination found")
-            };
-
-            let dest = {
-              let tmp = format!("{:x}", byte_dest);
-              if tmp.len() % 2 != 0 {
-                format!("0{}", tmp)
-              } else {
-                tmp
-              }
-            };
-
-            dest
-          } else if x.starts_with("dest-") {
-            "5B".to_owned()
-          } else {
-            x
-          }
-        })
-        .collect::<Vec<String>>()
-        .join(""),
-    )
-  }
-
-  fn compile_expression(&mut self, expression: &Expression) -> Result<Vec<String>, String> {
-    match expression.op {
-      Op::Num(i) => {
-        let num_vec = {
-          let mut bytes = Vec::new();
-          let tmp = format!("{:x}", i);
-          let mut byte = String::new();
-          for char in tmp.chars() {
-            byte.push(char);
-
-            if byte.len() == 2 {
-              bytes.push(byte);
-              byte = String::new();
-            }
-          }
-
-          if byte.len() > 0 {
-            bytes.push(format!("0{}", byte));
-          }
-          bytes
-        };
+    self.warn_unused_definitions();
+    self.warnings.sort_by_key(|warning| warning.primary.span.start);
 
-        let push_code = {
-          let num_bytes = num_vec.len();
-          if num_bytes > 32 {
-            Err("Number too large")
-          } else {
-            Ok(format!("{:x}", 96 + (num_bytes - 1)))
-          }
-        };
+    Ok(code)
+  }
+
+  fn new_label(&mut self) -> Label {
+    self.labels += 1;
+    Label(self.labels)
+  }
 
-        self.pc += num_vec.len() + 1;
+  fn compile_expression(&mut self, expression: &Expression) -> Result<Code, Diagnostic> {
+    // Blocks, bindings and macros pass being a statement on to the
+    // expression that gives their value; nothing else does.
+    if !matches!(expression.op, Op::Seq | Op::Let(_) | Op::Ident(_)) {
+      let statement = std::mem::replace(&mut self.statement, false);
 
-        Ok(vec![vec![push_code?], num_vec].concat())
-      }
-      Op::Add | Op::Div | Op::Sub | Op::Mul | Op::Mod | Op::And | Op::Or | Op::XOr => {
-        self.compile_multiary(expression)
+      if let (Op::Def(name, _), false) = (&expression.op, statement) {
+        return Err(
+          Diagnostic::error(
+            "E0012",
+            format!("The definition of `{}` is used as a value", name),
+            expression.span,
+          )
+          .with_label("definitions have no value")
+          .with_note("define names at the top level or as a statement in a block"),
+        );
       }
-      Op::Lt | Op::LtOE | Op::Gt | Op::GtOE | Op::Eq | Op::NotEq => self.compile_binary(expression),
-      Op::Not => self.compile_unary(expression),
+    }
+
+    let mut code = self.compile_op(expression)?;
+    code.claim(expression.span);
+    Ok(code)
+  }
+
+  fn compile_op(&mut self, expression: &Expression) -> Result<Code, Diagnostic> {
+    match &expression.op {
+      Op::Num(i) => Ok(Code::from(vec![Instruction::Push(*i)])),
+      Op::Add
+      | Op::Div
+      | Op::SDiv
+      | Op::Sub
+      | Op::Mul
+      | Op::Mod
+      | Op::SMod
+      | Op::And
+      | Op::Or
+      | Op::XOr => self.compile_multiary(expression),
+      Op::Lt
+      | Op::LtOE
+      | Op::Gt
+      | Op::GtOE
+      | Op::SLt
+      | Op::SLtOE
+      | Op::SGt
+      | Op::SGtOE
+      | Op::Eq
+      | Op::NotEq
+      | Op::SignExtend
+      | Op::MStore
+      | Op::SStore => self.compile_binary(expression),
+      Op::Not | Op::LogicalNot | Op::MLoad | Op::SLoad => self.compile_unary(expression),
+      Op::LogicalAnd | Op::LogicalOr => self.compile_logical(expression),
       Op::If => self.compile_if(expression),
       Op::When | Op::Unless => self.compile_when_or_unless(expression),
-      Op::End => Ok(Vec::new()),
-      _ => Err(String::from("Error")),
+      Op::While | Op::Until | Op::For => self.compile_loop(expression),
+      Op::Seq => self.compile_seq(expression),
+      Op::Def(name, params) => self.compile_def(name, params, expression),
+      Op::Ident(name) => self.compile_call(name, expression),
+      Op::Let(bindings) => self.compile_let(bindings, expression),
+      // One opaque block, so the optimizer never rewrites or drops any of it.
+      Op::Asm(code) => Ok(Code::from(vec![Instruction::Raw(assemble(code).byte_code)])),
+      Op::Builtin(opcode) => self.compile_builtin(*opcode, expression),
+      Op::ReturnLll | Op::Deploy => self.compile_deploy(expression),
+      Op::Dispatch(cases) => self.compile_dispatch(cases, expression),
+      Op::AbiEncode(types) => self.compile_abi_encode(types, expression),
+      Op::Emit(event) => self.compile_emit(event, expression),
+      Op::Return => self.compile_return(expression),
+      Op::Require(message) => self.compile_require(message.as_deref(), expression),
+      Op::AbiDecode(ty, index) => {
+        let mut code = Code::from(vec![
+          Instruction::Push(U256::from(4 + 32 * index)),
+          Instruction::Op(Opcode::CALLDATALOAD),
+        ]);
+        code.extend(ty.cleanup());
+        Ok(code)
+      }
+      Op::Start => Err(Diagnostic::error(
+        "E0001",
+        "Unexpected expression",
+        expression.span,
+      )),
     }
   }
 
-  fn compile_multiary(&mut self, arith_expr: &Expression) -> Result<Vec<String>, String> {
-    let mut byte_code: Vec<String> = Vec::new();
-    let mut counter = 0;
+  fn compile_multiary(&mut self, arith_expr: &Expression) -> Result<Code, Diagnostic> {
+    if arith_expr.exprs.is_empty() {
+      return Err(wrong_arity(arith_expr, "at least 1"));
+    }
 
-    if arith_expr.exprs.len() > 1 {
-      for expression in &arith_expr.exprs {
-        counter += 1;
-        byte_code = vec![self.compile_expression(&expression)?, byte_code].concat();
-      }
+    let op_code = match arith_expr.op {
+      Op::Add => Opcode::ADD,
+      Op::Mul => Opcode::MUL,
+      Op::Sub => Opcode::SUB,
+      Op::Div => Opcode::DIV,
+      Op::SDiv => Opcode::SDIV,
+      Op::Mod => Opcode::MOD,
+      Op::SMod => Opcode::SMOD,
+      Op::And => Opcode::AND,
+      Op::Or => Opcode::OR,
+      Op::XOr => Opcode::XOR,
+      _ => unreachable!("not an arithmetic expression"),
+    };
 
-      for _ in 0..(counter - 1) {
-        let op_code = {
-          match arith_expr.op {
-            Op::Add => "01".to_owned(),
-            Op::Mul => "02".to_owned(),
-            Op::Sub => "03".to_owned(),
-            Op::Div => "04".to_owned(),
-            Op::Mod => "06".to_owned(),
-            Op::And => "16".to_owned(),
-            Op::Or => "17".to_owned(),
-            Op::XOr => "18".to_owned(),
-            _ => return Err(String::from("Not arimetic expression")),
-          }
-        };
+    let mut code = Code::default();
 
-        self.pc += 1;
-        byte_code.push(op_code);
-      }
+    for expression in arith_expr.exprs.iter().rev() {
+      code.extend(self.compile_expression(expression)?);
+    }
 
-      Ok(byte_code)
-    } else {
-      self.compile_expression(&arith_expr.exprs[0])
+    for _ in 1..arith_expr.exprs.len() {
+      code.push(Instruction::Op(op_code));
     }
+
+    Ok(code)
   }
 
-  fn compile_if(&mut self, if_expr: &Expression) -> Result<Vec<String>, String> {
+  /// Compiles the arguments of a builtin so the first ends up on top of the
+  /// stack, followed by its opcode.
+  fn compile_builtin(
+    &mut self,
+    opcode: Opcode,
+    builtin_expr: &Expression,
+  ) -> Result<Code, Diagnostic> {
+    let mut code = Code::default();
+
+    for expression in builtin_expr.exprs.iter().rev() {
+      code.extend(self.compile_expression(expression)?);
+    }
+    code.push(Instruction::Op(opcode));
+
+    Ok(code)
+  }
+
+  fn compile_if(&mut self, if_expr: &Expression) -> Result<Code, Diagnostic> {
     if if_expr.exprs.len() != 3 {
-      return Err("Invalid number of arguments".to_owned());
+      return Err(wrong_arity(if_expr, "3"));
     }
 
     let comp_expr = self.compile_expression(&if_expr.exprs[0])?;
-    let else_expr = self.compile_expression(&if_expr.exprs[2])?;
-    let dest_then = self.pc;
     let then_expr = self.compile_expression(&if_expr.exprs[1])?;
-    let dest_next = self.pc;
-    let jump_then = vec!["60".to_owned(), format!("jump-{}", dest_then)];
-    let jump_next = vec!["60".to_owned(), format!("jump-{}", dest_next)];
+    let else_expr = self.compile_expression(&if_expr.exprs[2])?;
 
-    let mut byte_code = Vec::new();
-    byte_code.push(comp_expr);
-    byte_code.push(jump_then);
-    byte_code.push(vec!["57".to_owned()]);
-    byte_code.push(else_expr);
-    byte_code.push(jump_next);
-    byte_code.push(vec!["56".to_owned()]);
-    byte_code.push(vec![format!("dest-{}", dest_then)]);
-    byte_code.push(then_expr);
-    byte_code.push(vec![format!("dest-{}", dest_next)]);
+    // A branch that never falls through, say because it reverts, fits with
+    // anything.
+    match (net_effect(&then_expr.instructions), net_effect(&else_expr.instructions)) {
+      (Ok(Some(then_effect)), Ok(Some(else_effect))) if then_effect != else_effect => {
+        return Err(
+          Diagnostic::error(
+            "E0009",
+            "Branches of `if` leave different numbers of values on the stack",
+            if_expr.span,
+          )
+          .with_secondary(if_expr.exprs[1].span, format!("leaves {} value(s)", then_effect))
+          .with_secondary(if_expr.exprs[2].span, format!("leaves {} value(s)", else_effect)),
+        )
+      }
+      _ => {}
+    }
+
+    let dest_then = self.new_label();
+    let dest_next = self.new_label();
 
-    Ok(byte_code.concat())
+    let mut code = comp_expr;
+    code.push(Instruction::PushLabel(dest_then));
+    code.push(Instruction::Op(Opcode::JUMPI));
+    code.extend(else_expr);
+    code.push(Instruction::PushLabel(dest_next));
+    code.push(Instruction::Op(Opcode::JUMP));
+    code.push(Instruction::Label(dest_then));
+    code.extend(then_expr);
+    code.push(Instruction::Label(dest_next));
+
+    Ok(code)
   }
 
-  fn compile_when_or_unless(&mut self, when_expr: &Expression) -> Result<Vec<String>, String> {
+  fn compile_when_or_unless(&mut self, when_expr: &Expression) -> Result<Code, Diagnostic> {
     if when_expr.exprs.len() != 2 {
-      return Err("Invalid number of arguments".to_owned());
+      return Err(wrong_arity(when_expr, "2"));
     }
 
     let comp_expr = self.compile_expression(&when_expr.exprs[0])?;
-    let then_expr = self.compile_expression(&when_expr.exprs[1])?;
-
-    let dest_next = self.pc;
-    let jump_next = vec!["60".to_owned(), format!("jump-{}", dest_next)];
+    // The body only runs on one path, so it must leave nothing behind.
+    let then_expr = self.compile_statement(&when_expr.exprs[1])?;
+    let dest_next = self.new_label();
 
-    let mut byte_code = Vec::new();
-    byte_code.push(comp_expr);
+    let mut code = comp_expr;
 
     if let Op::When = when_expr.op {
-      byte_code.push(vec!["15".to_owned()]);
+      code.push(Instruction::Op(Opcode::ISZERO));
     }
-    
-    byte_code.push(jump_next);
-    byte_code.push(vec!["57".to_owned()]);
-    byte_code.push(then_expr);
-    byte_code.push(vec![format!("dest-{}", dest_next)]);
 
-    Ok(byte_code.concat())
+    code.push(Instruction::PushLabel(dest_next));
+    code.push(Instruction::Op(Opcode::JUMPI));
+    code.extend(then_expr);
+    code.push(Instruction::Label(dest_next));
+
+    Ok(code)
   }
 
-  fn compile_binary(&mut self, bin_expr: &Expression) -> Result<Vec<String>, String> {
-    if bin_expr.exprs.len() != 2 {
-      Err("Too many arguments in expression".to_owned())
-    } else {
-      let mut left: Option<Vec<String>> = None;
-      let mut right: Option<Vec<String>> = None;
-
-      let op_code = {
-        match bin_expr.op {
-          Op::Lt => "10".to_owned(),
-          Op::Gt => "11".to_owned(),
-          Op::Eq => "14".to_owned(),
-          Op::NotEq => "1415".to_owned(),
-          Op::LtOE | Op::GtOE => {
-            let comp_op = {
-              if let Op::LtOE = bin_expr.op {
-                Op::Lt
-              } else {
-                Op::Gt
-              }
-            };
-
-            let lt_or_gt_expr = Expression {
-              op: comp_op,
-              exprs: vec![bin_expr.exprs[0].clone(), bin_expr.exprs[1].clone()],
-            };
-
-            left = Some(self.compile_expression(&lt_or_gt_expr)?);
-
-            let eq_expr = Expression {
-              op: Op::Eq,
-              exprs: vec![bin_expr.exprs[0].clone(), bin_expr.exprs[1].clone()],
-            };
-
-            right = Some(self.compile_expression(&eq_expr)?);
-
-            "17".to_owned()
-          }
-          _ => return Err(String::from("Not binary expression")),
-        }
-      };
+  /// Compiles `(while cond body)`, `(until cond body)` and
+  /// `(for init cond post body)`. Loops leave nothing on the stack, so any
+  /// values left by the body, `init` or `post` are popped.
+  fn compile_loop(&mut self, loop_expr: &Expression) -> Result<Code, Diagnostic> {
+    let (init, cond, body, post) = match (&loop_expr.op, loop_expr.exprs.as_slice()) {
+      (Op::For, [init, cond, post, body]) => (Some(init), cond, body, Some(post)),
+      (Op::For, _) => return Err(wrong_arity(loop_expr, "4")),
+      (_, [cond, body]) => (None, cond, body, None),
+      _ => return Err(wrong_arity(loop_expr, "2")),
+    };
+
+    let dest_start = self.new_label();
+    let dest_end = self.new_label();
+
+    let mut code = match init {
+      Some(init) => self.compile_statement(init)?,
+      None => Code::default(),
+    };
+
+    code.push(Instruction::Label(dest_start));
+    code.extend(self.compile_expression(cond)?);
+
+    if !matches!(loop_expr.op, Op::Until) {
+      code.push(Instruction::Op(Opcode::ISZERO));
+    }
+
+    code.push(Instruction::PushLabel(dest_end));
+    code.push(Instruction::Op(Opcode::JUMPI));
+    code.extend(self.compile_statement(body)?);
+
+    if let Some(post) = post {
+      code.extend(self.compile_statement(post)?);
+    }
+
+    code.push(Instruction::PushLabel(dest_start));
+    code.push(Instruction::Op(Opcode::JUMP));
+    code.push(Instruction::Label(dest_end));
+
+    Ok(code)
+  }
+
+  /// Compiles `(returnlll runtime)` and `(deploy init runtime)` into init
+  /// code that runs `init`, copies the runtime into memory at 0 and returns
+  /// it. The runtime is compiled as a program of its own and placed after
+  /// the init code.
+  fn compile_deploy(&mut self, deploy_expr: &Expression) -> Result<Code, Diagnostic> {
+    let (init, runtime) = match (&deploy_expr.op, deploy_expr.exprs.as_slice()) {
+      (Op::ReturnLll, [runtime]) => (None, runtime),
+      (Op::ReturnLll, _) => return Err(wrong_arity(deploy_expr, "1")),
+      (_, [init, runtime]) => (Some(init), runtime),
+      _ => return Err(wrong_arity(deploy_expr, "2")),
+    };
+
+    let mut code = match init {
+      Some(init) => self.compile_statement(init)?,
+      None => Code::default(),
+    };
+
+    // Variables of the init code do not exist when the runtime runs.
+    let scope = std::mem::take(&mut self.scope);
+    let runtime_code = self.compile_program(runtime);
+    self.scope = scope;
+    let runtime_code = runtime_code?;
+
+    let runtime_label = self.new_label();
+
+    // CODECOPY(0, runtime, size) then RETURN(0, size).
+    code.push(Instruction::Push(U256::from(runtime_code.len())));
+    code.push(Instruction::Op(Opcode::DUP1));
+    code.push(Instruction::PushLabel(runtime_label));
+    code.push(Instruction::Push(U256::ZERO));
+    code.push(Instruction::Op(Opcode::CODECOPY));
+    code.push(Instruction::Push(U256::ZERO));
+    code.push(Instruction::Op(Opcode::RETURN));
+    code.push(Instruction::Data(runtime_label, runtime_code));
+
+    Ok(code)
+  }
+
+  /// Compiles `expression` as a complete program starting on an empty stack,
+  /// and assembles it.
+  fn compile_program(&mut self, expression: &Expression) -> Result<Vec<u8>, Diagnostic> {
+    let code = optimize(self.compile_expression(expression)?.instructions, self.level);
+    check_stack(&code, 0, expression)?;
+
+    Ok(assemble(&code).byte_code)
+  }
+
+  /// Compiles `(dispatch ...)`, which jumps to the handler whose selector
+  /// matches the first four bytes of the call data, or else to the fallback.
+  /// Without a fallback, such calls revert. Handlers stop once they finish,
+  /// or return the values they leave if their signature has outputs.
+  fn compile_dispatch(
+    &mut self,
+    cases: &[(Signature, Span)],
+    dispatch_expr: &Expression,
+  ) -> Result<Code, Diagnostic> {
+    for (i, (signature, span)) in cases.iter().enumerate() {
+      let selector = signature.selector();
+
+      if let Some((other, previous)) = cases[..i]
+        .iter()
+        .find(|(other, _)| other.selector() == selector)
+      {
+        return Err(
+          Diagnostic::error(
+            "E0006",
+            format!("`{}` is dispatched more than once", signature.canonical()),
+            *span,
+          )
+          .with_label(format!("selector 0x{}", to_hex(&selector)))
+          .with_secondary(*previous, format!("also the selector of `{}`", other.canonical())),
+        );
+      }
+    }
+
+    let mut entries = cases
+      .iter()
+      .map(|(signature, _)| Entry::Function(signature.clone()))
+      .collect::<Vec<Entry>>();
+
+    if dispatch_expr.exprs.len() > cases.len() {
+      entries.push(Entry::Fallback);
+    }
+
+    // A dispatch in a definition is compiled once for every use.
+    for entry in entries {
+      if !self.interface.contains(&entry) {
+        self.interface.push(entry);
+      }
+    }
+
+    let dest_fallback = self.new_label();
+    let dests = cases.iter().map(|_| self.new_label()).collect::<Vec<Label>>();
+
+    // Calls too short to hold a selector go straight to the fallback.
+    let mut code = Code::from(vec![
+      Instruction::Push(U256::from(4u64)),
+      Instruction::Op(Opcode::CALLDATASIZE),
+      Instruction::Op(Opcode::LT),
+      Instruction::PushLabel(dest_fallback),
+      Instruction::Op(Opcode::JUMPI),
+      Instruction::Push(U256::ZERO),
+      Instruction::Op(Opcode::CALLDATALOAD),
+      Instruction::Push(U256::from(0xe0u64)),
+      Instruction::Op(Opcode::SHR),
+    ]);
+
+    for ((signature, _), dest) in cases.iter().zip(&dests) {
+      code.push(Instruction::Op(Opcode::DUP1));
+      code.push(Instruction::Push(U256::from_be_bytes(&signature.selector())));
+      code.push(Instruction::Op(Opcode::EQ));
+      code.push(Instruction::PushLabel(*dest));
+      code.push(Instruction::Op(Opcode::JUMPI));
+    }
+
+    code.push(Instruction::Op(Opcode::POP));
+    code.push(Instruction::Label(dest_fallback));
+
+    match dispatch_expr.exprs.get(cases.len()) {
+      Some(fallback) => {
+        code.extend(self.compile_statement(fallback)?);
+        code.push(Instruction::Op(Opcode::STOP));
+      }
+      None => code.extend(vec![
+        Instruction::Push(U256::ZERO),
+        Instruction::Op(Opcode::DUP1),
+        Instruction::Op(Opcode::REVERT),
+      ]),
+    }
+
+    for (((signature, _), handler), dest) in cases.iter().zip(&dispatch_expr.exprs).zip(dests) {
+      code.push(Instruction::Label(dest));
+      code.push(Instruction::Op(Opcode::POP));
+      code.extend(self.compile_handler(signature, handler)?);
+    }
+
+    Ok(code)
+  }
+
+  /// Compiles the handler of a dispatch case, followed by a STOP, or by
+  /// code that returns its values if `signature` has outputs. The first
+  /// output is the value on top of the stack.
+  fn compile_handler(
+    &mut self,
+    signature: &Signature,
+    handler: &Expression,
+  ) -> Result<Code, Diagnostic> {
+    if signature.outputs.is_empty() {
+      let mut code = self.compile_statement(handler)?;
+      code.push(Instruction::Op(Opcode::STOP));
+      return Ok(code);
+    }
+
+    let mut code = self.compile_expression(handler)?;
+    let outputs = signature.outputs.len();
+
+    match net_effect(&code.instructions) {
+      // A handler that never finishes returns by itself.
+      Ok(None) => {}
+      Ok(Some(effect)) if effect == outputs as isize => {}
+      effect => {
+        let leaves = match effect {
+          Ok(Some(effect)) => format!("leaves {} value(s)", effect),
+          _ => "leaves an unknown number of values".to_owned(),
+        };
+
+        return Err(
+          Diagnostic::error(
+            "E0009",
+            format!(
+              "Handler for `{}` must leave {} value(s) to return",
+              signature.canonical(),
+              outputs
+            ),
+            handler.span,
+          )
+          .with_label(leaves),
+        );
+      }
+    }
+
+    for (i, output) in signature.outputs.iter().enumerate() {
+      code.extend(output.ty.cleanup());
+      code.push(Instruction::Push(U256::from(32 * i)));
+      code.push(Instruction::Op(Opcode::MSTORE));
+    }
+
+    code.push(Instruction::Push(U256::from(32 * outputs)));
+    code.push(Instruction::Push(U256::ZERO));
+    code.push(Instruction::Op(Opcode::RETURN));
+
+    Ok(code)
+  }
+
+  /// Compiles `(abi-encode "types" offset values...)`, which writes each
+  /// value, cleaned to its type, into consecutive words of memory from
+  /// `offset` and evaluates to the number of bytes written.
+  fn compile_abi_encode(
+    &mut self,
+    types: &[AbiType],
+    encode_expr: &Expression,
+  ) -> Result<Code, Diagnostic> {
+    let (offset, values) = match encode_expr.exprs.split_first() {
+      Some((offset, values)) if values.len() == types.len() => (offset, values),
+      _ => return Err(wrong_arity(encode_expr, &(types.len() + 1).to_string())),
+    };
+
+    let mut code = self.compile_expression(offset)?;
+
+    for (i, (ty, value)) in types.iter().zip(values).enumerate() {
+      code.extend(self.compile_expression(value)?);
+      code.extend(ty.cleanup());
+      code.push(Instruction::Op(Opcode::DUP2));
+
+      if i > 0 {
+        code.push(Instruction::Push(U256::from(32 * i)));
+        code.push(Instruction::Op(Opcode::ADD));
+      }
+
+      code.push(Instruction::Op(Opcode::MSTORE));
+    }
+
+    code.push(Instruction::Op(Opcode::POP));
+    code.push(Instruction::Push(U256::from(32 * types.len())));
+
+    Ok(code)
+  }
+
+  /// Compiles `(emit "event" values...)`. Indexed values become topics and
+  /// are evaluated first, in source order. The others follow, also in order,
+  /// and are ABI-encoded at the end of used memory once every value has been
+  /// computed, so that nothing they store can overwrite the data.
+  fn compile_emit(&mut self, event: &Event, emit_expr: &Expression) -> Result<Code, Diagnostic> {
+    if emit_expr.exprs.len() != event.inputs.len() {
+      return Err(wrong_arity(emit_expr, &event.inputs.len().to_string()));
+    }
 
-      if left.is_none() && right.is_none() {
-        left = Some(self.compile_expression(&bin_expr.exprs[0])?);
-        right = Some(self.compile_expression(&bin_expr.exprs[1])?);
+    let params = event.inputs.iter().zip(&emit_expr.exprs);
+    let mut code = Code::default();
+    let mut topics = 0;
+
+    for (param, value) in params.clone().filter(|(param, _)| param.indexed) {
+      code.extend(self.compile_expression(value)?);
+      code.extend(param.ty.cleanup());
+      topics += 1;
+    }
+
+    // LOG takes the first topic nearest the top.
+    code.extend(reverse_top(topics).into_iter().map(Instruction::Op));
+
+    if !event.anonymous {
+      code.push(Instruction::Push(U256::from_be_bytes(&event.topic())));
+      topics += 1;
+    }
+
+    let data = params.filter(|(param, _)| !param.indexed).collect::<Vec<_>>();
+    code.push(Instruction::Push(U256::from(32 * data.len())));
+
+    for (param, value) in &data {
+      code.extend(self.compile_expression(value)?);
+      code.extend(param.ty.cleanup());
+    }
+
+    // The values are on the stack with the last on top, just above the size.
+    code.push(Instruction::Op(Opcode::MSIZE));
+
+    for i in (0..data.len()).rev() {
+      code.push(Instruction::Op(Opcode::SWAP1));
+      code.push(Instruction::Op(Opcode::DUP2));
+
+      if i > 0 {
+        code.push(Instruction::Push(U256::from(32 * i)));
+        code.push(Instruction::Op(Opcode::ADD));
       }
 
-      self.pc += op_code.len() / 2;
-      let byte_code = vec![right.unwrap(), left.unwrap(), vec![op_code]].concat();
-      Ok(byte_code)
+      code.push(Instruction::Op(Opcode::MSTORE));
+    }
+
+    code.push(Instruction::Op(Opcode::log(topics)));
+
+    let entry = Entry::Event(event.clone());
+    if !self.interface.contains(&entry) {
+      self.interface.push(entry);
+    }
+
+    Ok(code)
+  }
+
+  /// Compiles `(return offset size)`, or `(return value)`, which returns the
+  /// value from memory at 0.
+  fn compile_return(&mut self, return_expr: &Expression) -> Result<Code, Diagnostic> {
+    match return_expr.exprs.as_slice() {
+      [value] => {
+        let mut code = self.compile_expression(value)?;
+        code.extend(vec![
+          Instruction::Push(U256::ZERO),
+          Instruction::Op(Opcode::MSTORE),
+          Instruction::Push(U256::from(32u32)),
+          Instruction::Push(U256::ZERO),
+          Instruction::Op(Opcode::RETURN),
+        ]);
+        Ok(code)
+      }
+      [_, _] => self.compile_builtin(Opcode::RETURN, return_expr),
+      _ => Err(wrong_arity(return_expr, "1 or 2")),
     }
   }
 
-  fn compile_unary(&mut self, unary_expr: &Expression) -> Result<Vec<String>, String> {
+  /// Compiles `(require cond "message")`. The revert data is that of
+  /// Solidity's `Error(string)`: its selector, then the message ABI-encoded
+  /// as an offset, a length and the bytes padded to whole words.
+  fn compile_require(
+    &mut self,
+    message: Option<&str>,
+    require_expr: &Expression,
+  ) -> Result<Code, Diagnostic> {
+    if require_expr.exprs.len() != 1 {
+      return Err(wrong_arity(require_expr, "1"));
+    }
+
+    let ok = self.new_label();
+    let mut code = self.compile_expression(&require_expr.exprs[0])?;
+    code.push(Instruction::PushLabel(ok));
+    code.push(Instruction::Op(Opcode::JUMPI));
+
+    let message = match message {
+      Some(message) => message.as_bytes(),
+      None => {
+        code.extend(vec![
+          Instruction::Push(U256::ZERO),
+          Instruction::Op(Opcode::DUP1),
+          Instruction::Op(Opcode::REVERT),
+          Instruction::Label(ok),
+        ]);
+        return Ok(code);
+      }
+    };
+
+    let selector = U256::from_be_bytes(&keccak256(b"Error(string)")[..4]) << 224;
+    let mut words = vec![selector, U256::from(32u32), U256::from(message.len())];
+
+    for chunk in message.chunks(32) {
+      let mut word = [0; 32];
+      word[..chunk.len()].copy_from_slice(chunk);
+      words.push(U256::from_be_bytes(&word));
+    }
+
+    for (i, word) in words.iter().enumerate() {
+      // Every word after the selector is offset by its four bytes.
+      let offset = if i == 0 { 0 } else { 4 + 32 * (i - 1) };
+      code.push(Instruction::Push(*word));
+      code.push(Instruction::Push(U256::from(offset)));
+      code.push(Instruction::Op(Opcode::MSTORE));
+    }
+
+    code.extend(vec![
+      Instruction::Push(U256::from(4 + 32 * (words.len() - 1))),
+      Instruction::Push(U256::ZERO),
+      Instruction::Op(Opcode::REVERT),
+      Instruction::Label(ok),
+    ]);
+
+    Ok(code)
+  }
+
+  /// Compiles each expression in order, popping what all but the last leave
+  /// on the stack.
+  fn compile_seq(&mut self, seq_expr: &Expression) -> Result<Code, Diagnostic> {
+    let statement = std::mem::replace(&mut self.statement, false);
+    let mut code = Code::default();
+
+    if let Some((last, rest)) = seq_expr.exprs.split_last() {
+      for expression in rest {
+        code.extend(self.compile_statement(expression)?);
+      }
+      self.statement = statement;
+      code.extend(self.compile_expression(last)?);
+    }
+
+    Ok(code)
+  }
+
+  /// Compiles an expression for its side effects, popping whatever it leaves
+  /// on the stack.
+  fn compile_statement(&mut self, expression: &Expression) -> Result<Code, Diagnostic> {
+    self.statement = true;
+    let mut code = self.compile_expression(expression)?;
+
+    match net_effect(&code.instructions) {
+      Ok(effect) if effect.unwrap_or(0) >= 0 => {
+        code.extend((0..effect.unwrap_or(0)).map(|_| Instruction::Op(Opcode::POP)));
+        Ok(code)
+      }
+      Ok(_) => Err(Diagnostic::error(
+        "E0009",
+        "This expression takes values from the stack that it did not push",
+        expression.span,
+      )),
+      Err(_) => Err(
+        Diagnostic::error(
+          "E0009",
+          "Cannot tell how many values this expression leaves on the stack",
+          expression.span,
+        )
+        .with_note("its paths leave different numbers of values, or it jumps to an unknown target"),
+      ),
+    }
+  }
+
+  fn compile_binary(&mut self, bin_expr: &Expression) -> Result<Code, Diagnostic> {
+    if bin_expr.exprs.len() != 2 {
+      return Err(wrong_arity(bin_expr, "2"));
+    }
+
+    let op_codes = match bin_expr.op {
+      Op::Lt => vec![Opcode::LT],
+      Op::Gt => vec![Opcode::GT],
+      Op::SLt => vec![Opcode::SLT],
+      Op::SGt => vec![Opcode::SGT],
+      // a <= b is !(a > b), so each operand is evaluated only once.
+      Op::LtOE => vec![Opcode::GT, Opcode::ISZERO],
+      Op::GtOE => vec![Opcode::LT, Opcode::ISZERO],
+      Op::SLtOE => vec![Opcode::SGT, Opcode::ISZERO],
+      Op::SGtOE => vec![Opcode::SLT, Opcode::ISZERO],
+      Op::Eq => vec![Opcode::EQ],
+      Op::NotEq => vec![Opcode::EQ, Opcode::ISZERO],
+      Op::SignExtend => vec![Opcode::SIGNEXTEND],
+      Op::MStore => vec![Opcode::MSTORE],
+      Op::SStore => vec![Opcode::SSTORE],
+      _ => unreachable!("not a binary expression"),
+    };
+
+    let left = self.compile_expression(&bin_expr.exprs[0])?;
+    let right = self.compile_expression(&bin_expr.exprs[1])?;
+
+    let mut code = right;
+    code.extend(left);
+    code.extend(op_codes.into_iter().map(Instruction::Op));
+
+    Ok(code)
+  }
+
+  fn compile_unary(&mut self, unary_expr: &Expression) -> Result<Code, Diagnostic> {
     if unary_expr.exprs.len() != 1 {
-      return Err("Invalid number of arguments".to_owned());
+      return Err(wrong_arity(unary_expr, "1"));
+    }
+
+    let op_code = match unary_expr.op {
+      Op::Not => Opcode::NOT,
+      Op::LogicalNot => Opcode::ISZERO,
+      Op::MLoad => Opcode::MLOAD,
+      Op::SLoad => Opcode::SLOAD,
+      _ => unreachable!("not a unary expression"),
+    };
+
+    let mut code = self.compile_expression(&unary_expr.exprs[0])?;
+    code.push(Instruction::Op(op_code));
+
+    Ok(code)
+  }
+
+  /// Compiles `(and ...)` or `(or ...)`. Each value is normalized to 0 or 1
+  /// and left on the stack; if it decides the result, it is also the result
+  /// and the rest are skipped. With no values, `and` is 1 and `or` is 0.
+  fn compile_logical(&mut self, logical_expr: &Expression) -> Result<Code, Diagnostic> {
+    let is_and = matches!(logical_expr.op, Op::LogicalAnd);
+
+    let (last, rest) = match logical_expr.exprs.split_last() {
+      Some(split) => split,
+      None => return Ok(Code::from(vec![Instruction::Push(U256::from(is_and as u64))])),
+    };
+
+    let dest_end = self.new_label();
+    let mut code = Code::default();
+
+    for expression in rest {
+      code.extend(self.compile_expression(expression)?);
+      code.push(Instruction::Op(Opcode::ISZERO));
+      code.push(Instruction::Op(Opcode::ISZERO));
+      code.push(Instruction::Op(Opcode::DUP1));
+
+      if is_and {
+        code.push(Instruction::Op(Opcode::ISZERO));
+      }
+
+      code.push(Instruction::PushLabel(dest_end));
+      code.push(Instruction::Op(Opcode::JUMPI));
+      code.push(Instruction::Op(Opcode::POP));
+    }
+
+    code.extend(self.compile_expression(last)?);
+    code.push(Instruction::Op(Opcode::ISZERO));
+    code.push(Instruction::Op(Opcode::ISZERO));
+
+    if !rest.is_empty() {
+      code.push(Instruction::Label(dest_end));
+    }
+
+    Ok(code)
+  }
+
+  fn compile_def(
+    &mut self,
+    name: &str,
+    params: &[String],
+    def_expr: &Expression,
+  ) -> Result<Code, Diagnostic> {
+    if let Some(previous) = self.definitions.get(name) {
+      return Err(
+        Diagnostic::error(
+          "E0006",
+          format!("`{}` is defined multiple times", name),
+          def_expr.span,
+        )
+        .with_label("redefined here")
+        .with_secondary(previous.span, "previous definition here"),
+      );
+    }
+
+    let free = free_names(&def_expr.exprs[0], params);
+    self.check_free_names(name, &free)?;
+
+    self.definitions.insert(
+      name.to_owned(),
+      Definition {
+        params: params.to_vec(),
+        body: def_expr.exprs[0].clone(),
+        free,
+        span: def_expr.span,
+        used: false,
+      },
+    );
+
+    Ok(Code::default())
+  }
+
+  /// Evaluates each value into a fresh memory slot in order, so later values
+  /// can refer to earlier bindings, then compiles the body with the bindings
+  /// in scope.
+  fn compile_let(
+    &mut self,
+    bindings: &[(String, Span)],
+    let_expr: &Expression,
+  ) -> Result<Code, Diagnostic> {
+    let statement = std::mem::replace(&mut self.statement, false);
+    let outer = self.scope.len();
+    let mut code = Code::default();
+
+    for (i, (name, span)) in bindings.iter().enumerate() {
+      if let Some((_, previous)) = bindings[..i].iter().find(|(other, _)| other == name) {
+        return Err(
+          Diagnostic::error(
+            "E0006",
+            format!("`{}` is bound more than once", source_name(name)),
+            *span,
+          )
+            .with_label("bound again here")
+            .with_secondary(*previous, "first bound here"),
+        );
+      }
+
+      let value = self.compile_expression(&let_expr.exprs[i])?;
+      self.warn_if_shadowing(name, *span);
+
+      let slot = U256::from(LOCALS_BASE + 32 * self.scope.len() as u64);
+      code.extend(value);
+      code.push(Instruction::Push(slot));
+      code.push(Instruction::Op(Opcode::MSTORE));
+
+      self.scope.push(Variable {
+        name: name.clone(),
+        span: *span,
+        slot,
+      });
+    }
+
+    self.statement = statement;
+    code.extend(self.compile_expression(&let_expr.exprs[bindings.len()])?);
+    self.scope.truncate(outer);
+
+    Ok(code)
+  }
+
+  fn variable(&self, name: &str) -> Option<&Variable> {
+    self.scope.iter().rev().find(|variable| variable.name == name)
+  }
+
+  /// Checks that none of the `free` names in the body of `name` is a variable
+  /// here, which the body would otherwise pick up.
+  fn check_free_names(&self, name: &str, free: &[(String, Span)]) -> Result<(), Diagnostic> {
+    for (free_name, span) in free {
+      if let Some(variable) = self.variable(free_name) {
+        return Err(
+          Diagnostic::error(
+            "E0013",
+            format!(
+              "The body of `{}` refers to the variable `{}`",
+              name,
+              source_name(free_name)
+            ),
+            *span,
+          )
+            .with_label("not a parameter")
+            .with_secondary(variable.span, "bound here")
+            .with_note(format!("pass `{}` to `{}` as an argument", source_name(free_name), name)),
+        );
+      }
     }
 
-    let byte_code = vec![
-      self.compile_expression(&unary_expr.exprs[0])?,
-      vec!["19".to_owned()],
-    ]
-    .concat();
+    Ok(())
+  }
 
-    self.pc += 1;
+  fn warn_if_shadowing(&mut self, name: &str, span: Span) {
+    let previous = match (self.variable(name), self.definitions.get(source_name(name))) {
+      (Some(variable), _) => Some((variable.span, "previous binding here")),
+      (None, Some(definition)) => Some((definition.span, "definition here")),
+      (None, None) => None,
+    };
 
-    Ok(byte_code)
+    // A definition expanded more than once would otherwise repeat the warning.
+    let reported = self
+      .warnings
+      .iter()
+      .any(|warning| warning.code == "W0002" && warning.primary.span == span);
+
+    if let Some((previous, label)) = previous.filter(|_| !reported) {
+      self.warnings.push(
+        Diagnostic::warning(
+          "W0002",
+          format!("`{}` shadows an earlier name", source_name(name)),
+          span,
+        )
+          .with_secondary(previous, label)
+          .with_note("the earlier name cannot be used in this scope"),
+      );
+    }
   }
+
+  fn compile_call(
+    &mut self,
+    name: &str,
+    call_expr: &Expression,
+  ) -> Result<Code, Diagnostic> {
+    if self.variable(name).is_some() {
+      self.statement = false;
+    }
+
+    if let Some(variable) = self.variable(name) {
+      if !call_expr.exprs.is_empty() {
+        return Err(
+          Diagnostic::error(
+            "E0004",
+            format!("`{}` is a variable and takes no arguments", source_name(name)),
+            call_expr.span,
+          )
+          .with_secondary(variable.span, "bound here"),
+        );
+      }
+
+      return Ok(Code::from(vec![
+        Instruction::Push(variable.slot),
+        Instruction::Op(Opcode::MLOAD),
+      ]));
+    }
+
+    let expanded = {
+      let definition = self.definitions.get_mut(name).ok_or_else(|| {
+        Diagnostic::error(
+          "E0005",
+          format!("Cannot find `{}` in this scope", name),
+          call_expr.span,
+        )
+        .with_label("not defined")
+      })?;
+      definition.used = true;
+
+      if definition.params.len() != call_expr.exprs.len() {
+        return Err(
+          Diagnostic::error(
+            "E0004",
+            format!(
+              "`{}` takes {} argument(s) but {} were supplied",
+              name,
+              definition.params.len(),
+              call_expr.exprs.len()
+            ),
+            call_expr.span,
+          )
+          .with_secondary(definition.span, "defined here"),
+        );
+      }
+
+      self.expansions += 1;
+      substitute(&definition.body, &definition.params, &call_expr.exprs, self.expansions)
+    };
+
+    self
+      .check_free_names(name, &self.definitions[name].free)
+      .map_err(|e| e.with_secondary(call_expr.span, format!("in this expansion of `{}`", name)))?;
+
+    if self.depth >= MAX_EXPANSION_DEPTH {
+      return Err(
+        Diagnostic::error(
+          "E0007",
+          format!("Expansion of `{}` is too deeply nested", name),
+          call_expr.span,
+        )
+        .with_note(format!("is `{}` recursive?", name)),
+      );
+    }
+
+    self.depth += 1;
+    let byte_code = self.compile_expression(&expanded);
+    self.depth -= 1;
+
+    if self.depth == 0 {
+      byte_code.map_err(|e| {
+        e.with_secondary(call_expr.span, format!("in this expansion of `{}`", name))
+      })
+    } else {
+      byte_code
+    }
+  }
+
+  fn warn_unused_definitions(&mut self) {
+    let mut unused = self
+      .definitions
+      .iter()
+      .filter(|(name, definition)| !definition.used && !name.starts_with('_'))
+      .map(|(name, definition)| {
+        Diagnostic::warning(
+          "W0001",
+          format!("Definition `{}` is never used", name),
+          definition.span,
+        )
+        .with_note("prefix the name with an underscore to silence this warning")
+      })
+      .collect::<Vec<Diagnostic>>();
+
+    self.warnings.append(&mut unused);
+  }
+}
+
+/// Checks that `code` never pops more than is on the stack, given that it
+/// starts at `height`, and that every path leaves the stack at the same
+/// height. Returns the height it leaves, or `None` if that cannot be known.
+fn check_stack(
+  code: &[Instruction],
+  height: usize,
+  expr: &Expression,
+) -> Result<Option<usize>, Diagnostic> {
+  match heights(code, height) {
+    Ok(heights) => Ok(heights[code.len()]),
+    Err(StackError::Underflow { height, needed, .. }) => Err(
+      Diagnostic::error("E0010", "Stack underflow", expr.span)
+        .with_label(format!(
+          "an instruction here needs {} value(s) but only {} are on the stack",
+          needed, height
+        )),
+    ),
+    Err(StackError::Mismatch {
+      expected, found, ..
+    }) => Err(
+      Diagnostic::error(
+        "E0009",
+        "Paths through this expression leave different stack heights",
+        expr.span,
+      )
+      .with_label(format!("reached with both {} and {} values", expected, found)),
+    ),
+    // Computed jumps in inline assembly can go anywhere.
+    Err(StackError::Unknown { .. }) => Ok(None),
+  }
+}
+
+fn wrong_arity(expr: &Expression, expected: &str) -> Diagnostic {
+  Diagnostic::error(
+    "E0004",
+    format!(
+      "Invalid number of arguments: expected {}, found {}",
+      expected,
+      expr.exprs.len()
+    ),
+    expr.span,
+  )
+}
+
+/// Swaps that reverse the order of the top `count` items of the stack.
+fn reverse_top(count: usize) -> Vec<Opcode> {
+  (0..count / 2)
+    .flat_map(|i| match (i, count - 1 - i) {
+      (0, j) => vec![Opcode::swap(j)],
+      // Exchanges the items `i` and `j` deep by way of the top.
+      (i, j) => vec![Opcode::swap(i), Opcode::swap(j), Opcode::swap(i)],
+    })
+    .collect()
+}
+
+/// Replaces every bare reference to a parameter in `body` with the matching
+/// argument expression.
+///
+/// Names bound by a `let` in the body are renamed for this `expansion`, so
+/// that they cannot capture a variable of the same name in an argument. Any
+/// other name is left as is and resolves where the macro is used, so it may
+/// name a definition but never a variable: `compile_def` and `compile_call`
+/// reject that with E0013 rather than let the body capture one.
+fn substitute(
+  body: &Expression,
+  params: &[String],
+  args: &[Expression],
+  expansion: usize,
+) -> Expression {
+  let args = params.iter().cloned().zip(args.iter().cloned()).collect();
+  substitute_in(body, &args, &HashMap::new(), expansion)
+}
+
+/// `substitute`, with `renamed` mapping the names bound so far in the body
+/// to their new names.
+fn substitute_in(
+  body: &Expression,
+  args: &HashMap<String, Expression>,
+  renamed: &HashMap<String, String>,
+  expansion: usize,
+) -> Expression {
+  if let Op::Let(bindings) = &body.op {
+    return substitute_let(body, bindings, args, renamed, expansion);
+  }
+
+  let op = match &body.op {
+    Op::Ident(name) => match (renamed.get(name), args.get(name)) {
+      (Some(fresh), _) => Op::Ident(fresh.clone()),
+      (None, Some(arg)) if body.exprs.is_empty() => return arg.clone(),
+      _ => body.op.clone(),
+    },
+    op => op.clone(),
+  };
+
+  Expression::new(
+    op,
+    body
+      .exprs
+      .iter()
+      .map(|expr| substitute_in(expr, args, renamed, expansion))
+      .collect(),
+    body.span,
+  )
+}
+
+/// Substitutes into a `let`, renaming each binding and every reference to it
+/// from that binding onwards.
+fn substitute_let(
+  let_expr: &Expression,
+  bindings: &[(String, Span)],
+  args: &HashMap<String, Expression>,
+  renamed: &HashMap<String, String>,
+  expansion: usize,
+) -> Expression {
+  let mut renamed = renamed.clone();
+  let mut fresh_bindings = Vec::new();
+  let mut exprs = Vec::new();
+
+  for (i, expr) in let_expr.exprs.iter().enumerate() {
+    exprs.push(substitute_in(expr, args, &renamed, expansion));
+
+    if let Some((name, span)) = bindings.get(i) {
+      // No identifier in the source can contain a space.
+      let fresh = format!("{} {}", source_name(name), expansion);
+      renamed.insert(name.clone(), fresh.clone());
+      fresh_bindings.push((fresh, *span));
+    }
+  }
+
+  Expression::new(Op::Let(fresh_bindings), exprs, let_expr.span)
+}
+
+/// The names `body` uses without binding them, other than `params`.
+fn free_names(body: &Expression, params: &[String]) -> Vec<(String, Span)> {
+  let mut free = Vec::new();
+  collect_free_names(body, params, &mut free);
+  free
+}
+
+fn collect_free_names(expr: &Expression, bound: &[String], free: &mut Vec<(String, Span)>) {
+  match &expr.op {
+    // Each binding is in scope from the next value onwards.
+    Op::Let(bindings) => {
+      let mut bound = bound.to_vec();
+
+      for (i, expr) in expr.exprs.iter().enumerate() {
+        collect_free_names(expr, &bound, free);
+
+        if let Some((name, _)) = bindings.get(i) {
+          bound.push(name.clone());
+        }
+      }
+
+      return;
+    }
+    Op::Def(_, params) => {
+      let bound = [bound, params].concat();
+
+      for expr in &expr.exprs {
+        collect_free_names(expr, &bound, free);
+      }
+
+      return;
+    }
+    Op::Ident(name) if !bound.contains(name) => free.push((name.clone(), expr.span)),
+    _ => {}
+  }
+
+  for expr in &expr.exprs {
+    collect_free_names(expr, bound, free);
+  }
+}
+
+/// The name as written in the source, without the suffix a macro expansion
+/// gives the names it binds.
+fn source_name(name: &str) -> &str {
+  name.split(' ').next().unwrap_or(name)
 }
@@ -0,0 +1,223 @@
+use std::fmt;
+
+/// A region of source text. `start` and `end` are byte offsets, `row` and
+/// `col` are the 1-based line and column of `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+  pub row: u32,
+  pub col: u32,
+}
+
+impl Span {
+  pub fn new(start: usize, end: usize, row: u32, col: u32) -> Self {
+    Span {
+      start,
+      end,
+      row,
+      col,
+    }
+  }
+
+  /// The smallest span covering both `self` and `other`.
+  pub fn to(self, other: Span) -> Span {
+    if other.start < self.start {
+      return other.to(self);
+    }
+
+    Span {
+      end: self.end.max(other.end),
+      ..self
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Severity::Error => write!(f, "error"),
+      Severity::Warning => write!(f, "warning"),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+  pub span: Span,
+  pub message: String,
+}
+
+/// A message about the source being compiled.
+///
+/// Codes currently in use:
+///
+/// | Code  | Meaning                                  |
+/// |-------|------------------------------------------|
+/// | E0001 | unexpected token                         |
+/// | E0002 | unbalanced delimiter                     |
+/// | E0003 | invalid token or literal                 |
+/// | E0004 | wrong number of arguments                |
+/// | E0005 | undefined identifier                     |
+/// | E0006 | identifier defined more than once        |
+/// | E0007 | macro expansion nested too deeply        |
+/// | E0008 | invalid inline assembly                  |
+/// | E0009 | inconsistent stack height                |
+/// | E0010 | stack underflow                          |
+/// | E0011 | invalid ABI signature or type            |
+/// | E0012 | definition used as a value               |
+/// | E0013 | definition refers to a variable          |
+/// | W0001 | definition is never used                 |
+/// | W0002 | name shadows an earlier binding          |
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub code: &'static str,
+  pub message: String,
+  pub primary: Label,
+  pub secondary: Vec<Label>,
+  pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+  pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+    Self::new(Severity::Error, code, message.into(), span)
+  }
+
+  pub fn warning(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+    Self::new(Severity::Warning, code, message.into(), span)
+  }
+
+  fn new(severity: Severity, code: &'static str, message: String, span: Span) -> Self {
+    Diagnostic {
+      severity,
+      code,
+      message,
+      primary: Label {
+        span,
+        message: String::new(),
+      },
+      secondary: vec![],
+      notes: vec![],
+    }
+  }
+
+  /// Sets the message printed under the primary span.
+  pub fn with_label(mut self, message: impl Into<String>) -> Self {
+    self.primary.message = message.into();
+    self
+  }
+
+  pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+    self.secondary.push(Label {
+      span,
+      message: message.into(),
+    });
+    self
+  }
+
+  pub fn with_note(mut self, note: impl Into<String>) -> Self {
+    self.notes.push(note.into());
+    self
+  }
+
+  /// Renders the diagnostic in the style of rustc, quoting the offending
+  /// source lines and underlining each labelled span.
+  pub fn render(&self, file_name: &str, source: &str) -> String {
+    let gutter = std::iter::once(&self.primary)
+      .chain(self.secondary.iter())
+      .map(|label| label.span.row.to_string().len())
+      .max()
+      .unwrap_or(1);
+    let pad = " ".repeat(gutter);
+
+    let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+    out += &format!(
+      "{}--> {}:{}:{}\n",
+      pad, file_name, self.primary.span.row, self.primary.span.col
+    );
+    out += &format!("{} |\n", pad);
+
+    // Lines are quoted in source order, each once, with an underline for
+    // every label on it.
+    let mut labels = std::iter::once((&self.primary, '^'))
+      .chain(self.secondary.iter().map(|label| (label, '-')))
+      .collect::<Vec<(&Label, char)>>();
+    labels.sort_by_key(|(label, _)| (label.span.row, label.span.start));
+
+    for (i, (label, marker)) in labels.iter().enumerate() {
+      if i == 0 || labels[i - 1].0.span.row != label.span.row {
+        out += &render_line(label, source, gutter);
+      }
+      out += &render_underline(label, *marker, source, gutter);
+    }
+
+    if !self.notes.is_empty() {
+      out += &format!("{} |\n", pad);
+    }
+    for note in &self.notes {
+      out += &format!("{} = note: {}\n", pad, note);
+    }
+
+    out
+  }
+}
+
+/// The start of the line `label` begins on, and the end of that line.
+fn line_bounds(label: &Label, source: &str) -> (usize, usize) {
+  let start = label.span.start.min(source.len());
+  let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+  let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+
+  (line_start, line_end)
+}
+
+fn render_line(label: &Label, source: &str, gutter: usize) -> String {
+  let (line_start, line_end) = line_bounds(label, source);
+  let line = source[line_start..line_end].trim_end_matches('\r');
+
+  format!("{:>gutter$} | {}\n", label.span.row, line, gutter = gutter)
+}
+
+fn render_underline(label: &Label, marker: char, source: &str, gutter: usize) -> String {
+  let start = label.span.start.min(source.len());
+  let (line_start, line_end) = line_bounds(label, source);
+
+  let indent = source[line_start..start]
+    .chars()
+    .map(|c| if c == '\t' { '\t' } else { ' ' })
+    .collect::<String>();
+  let width = source[start..label.span.end.clamp(start, line_end)]
+    .chars()
+    .count()
+    .max(1);
+
+  let mut out = format!(
+    "{} | {}{}",
+    " ".repeat(gutter),
+    indent,
+    marker.to_string().repeat(width)
+  );
+  if !label.message.is_empty() {
+    out += &format!(" {}", label.message);
+  }
+  out.push('\n');
+
+  out
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{}[{}]: {} at {}:{}",
+      self.severity, self.code, self.message, self.primary.span.row, self.primary.span.col
+    )
+  }
+}
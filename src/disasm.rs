@@ -0,0 +1,96 @@
+use crate::opcode::Opcode;
+use crate::output::to_hex;
+use crate::uint::U256;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// One decoded instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+  pub offset: usize,
+  pub opcode: Opcode,
+  /// Immediate data. Shorter than the opcode expects if the code ends in the
+  /// middle of a PUSH.
+  pub data: Vec<u8>,
+}
+
+impl Line {
+  fn is_truncated(&self) -> bool {
+    self.data.len() < self.opcode.immediate_size()
+  }
+}
+
+/// Splits `code` into instructions.
+pub fn disassemble(code: &[u8]) -> Vec<Line> {
+  let mut lines = Vec::new();
+  let mut offset = 0;
+
+  while offset < code.len() {
+    let opcode = Opcode(code[offset]);
+    let end = (offset + 1 + opcode.immediate_size()).min(code.len());
+
+    lines.push(Line {
+      offset,
+      opcode,
+      data: code[offset + 1..end].to_vec(),
+    });
+    offset = end;
+  }
+
+  lines
+}
+
+/// Renders `code` as annotated assembly, one instruction per line.
+///
+/// Every JUMPDEST is preceded by a `loc_` label, and pushes of a JUMPDEST's
+/// offset are annotated with that label. Bytes that are not opcodes are
+/// shown as `UNKNOWN`.
+pub fn render(code: &[u8]) -> String {
+  let lines = disassemble(code);
+  let width = format!("{:x}", code.len()).len().max(4);
+  let dests = lines
+    .iter()
+    .filter(|line| line.opcode == Opcode::JUMPDEST)
+    .map(|line| line.offset)
+    .collect::<HashSet<usize>>();
+
+  let mut out = String::new();
+
+  for line in &lines {
+    if line.opcode == Opcode::JUMPDEST {
+      let _ = writeln!(out, "loc_{:0width$x}:", line.offset, width = width);
+    }
+
+    let mut text = match line.opcode.info() {
+      Some(info) => info.name.to_owned(),
+      None => format!("UNKNOWN 0x{:02x}", line.opcode.0),
+    };
+
+    if line.opcode.immediate_size() > 0 {
+      let _ = write!(text, " 0x{}", to_hex(&line.data));
+    }
+
+    let target = U256::from_be_bytes(&line.data).to_usize();
+    let comment = if line.is_truncated() {
+      Some("truncated".to_owned())
+    } else {
+      target
+        .filter(|target| line.opcode.immediate_size() > 0 && dests.contains(target))
+        .map(|target| format!("loc_{:0width$x}", target, width = width))
+    };
+
+    let _ = match comment {
+      Some(comment) => writeln!(
+        out,
+        "  {:0width$x}  {:<24}; {}",
+        line.offset,
+        text,
+        comment,
+        width = width
+      ),
+      None => writeln!(out, "  {:0width$x}  {}", line.offset, text, width = width),
+    };
+  }
+
+  out
+}
@@ -0,0 +1,568 @@
+use crate::keccak::keccak256;
+use crate::opcode::Opcode;
+use crate::output::to_hex;
+use crate::uint::U256;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+
+/// Maximum number of items on the stack, as on mainnet.
+const STACK_LIMIT: usize = 1024;
+
+/// Without gas there is nothing to stop an infinite loop, so execution gives
+/// up after this many instructions.
+const STEP_LIMIT: usize = 10_000_000;
+
+/// Largest amount of memory a program may touch, in bytes.
+const MEMORY_LIMIT: usize = 1 << 24;
+
+/// How execution ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Halt {
+  /// STOP, or running off the end of the code.
+  Stop,
+  Return,
+  Revert,
+}
+
+/// The state left behind by a program that halted normally.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution {
+  pub halt: Halt,
+  /// The final stack, bottom first.
+  pub stack: Vec<U256>,
+  pub return_data: Vec<u8>,
+  /// Storage after execution. Empty if the program reverted.
+  pub storage: BTreeMap<U256, U256>,
+  /// Logs in the order they were emitted. Empty if the program reverted.
+  pub logs: Vec<Log>,
+}
+
+/// An entry written by one of the LOG opcodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Log {
+  pub topics: Vec<U256>,
+  pub data: Vec<u8>,
+}
+
+/// Why execution stopped abnormally. `pc` is the offset of the instruction
+/// that failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+  StackUnderflow { pc: usize },
+  StackOverflow { pc: usize },
+  InvalidJump { pc: usize, target: U256 },
+  /// The designated INVALID instruction.
+  InvalidOpcode { pc: usize },
+  /// An opcode outside the subset this interpreter implements.
+  Unsupported { pc: usize, opcode: u8 },
+  MemoryLimit { pc: usize },
+  StepLimit,
+}
+
+impl fmt::Display for VmError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      VmError::StackUnderflow { pc } => write!(f, "stack underflow at {:#x}", pc),
+      VmError::StackOverflow { pc } => write!(f, "stack overflow at {:#x}", pc),
+      VmError::InvalidJump { pc, target } => {
+        write!(f, "jump at {:#x} to {:#x} is not a JUMPDEST", pc, target)
+      }
+      VmError::InvalidOpcode { pc } => write!(f, "INVALID instruction at {:#x}", pc),
+      VmError::Unsupported { pc, opcode } => {
+        write!(f, "unsupported opcode 0x{:02x} at {:#x}", opcode, pc)
+      }
+      VmError::MemoryLimit { pc } => {
+        write!(f, "memory access at {:#x} exceeds {} bytes", pc, MEMORY_LIMIT)
+      }
+      VmError::StepLimit => write!(f, "gave up after {} steps", STEP_LIMIT),
+    }
+  }
+}
+
+/// The environment and block a program runs in. Everything defaults to zero.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Context {
+  pub address: U256,
+  /// The balance of `address`. Every other account has none.
+  pub balance: U256,
+  pub origin: U256,
+  pub caller: U256,
+  pub callvalue: U256,
+  pub gasprice: U256,
+  pub coinbase: U256,
+  pub timestamp: U256,
+  pub number: U256,
+  pub prevrandao: U256,
+  pub gaslimit: U256,
+  pub chainid: U256,
+  pub basefee: U256,
+  pub blobbasefee: U256,
+  /// What GAS reports. Since gas is not accounted for, it never goes down.
+  pub gas: U256,
+}
+
+impl Context {
+  /// The value an opcode that reads the environment pushes.
+  fn get(&self, opcode: Opcode) -> Option<U256> {
+    let value = match opcode {
+      Opcode::ADDRESS => self.address,
+      Opcode::ORIGIN => self.origin,
+      Opcode::CALLER => self.caller,
+      Opcode::CALLVALUE => self.callvalue,
+      Opcode::GASPRICE => self.gasprice,
+      Opcode::COINBASE => self.coinbase,
+      Opcode::TIMESTAMP => self.timestamp,
+      Opcode::NUMBER => self.number,
+      Opcode::PREVRANDAO => self.prevrandao,
+      Opcode::GASLIMIT => self.gaslimit,
+      Opcode::CHAINID => self.chainid,
+      Opcode::SELFBALANCE => self.balance,
+      Opcode::BASEFEE => self.basefee,
+      Opcode::BLOBBASEFEE => self.blobbasefee,
+      Opcode::GAS => self.gas,
+      // Nothing is ever called, so there is never any return data.
+      Opcode::RETURNDATASIZE => U256::ZERO,
+      _ => return None,
+    };
+
+    Some(value)
+  }
+}
+
+/// An interpreter for the subset of the EVM the compiler targets: stack,
+/// memory, storage, arithmetic, comparisons, jumps, call data, the
+/// environment in a `Context`, KECCAK256, logs and RETURN. There is no gas
+/// accounting and no other account to call.
+pub struct Vm<'a> {
+  code: &'a [u8],
+  calldata: &'a [u8],
+  context: Context,
+  jump_dests: Vec<bool>,
+  pc: usize,
+  stack: Vec<U256>,
+  memory: Vec<u8>,
+  storage: BTreeMap<U256, U256>,
+  logs: Vec<Log>,
+}
+
+impl<'a> Vm<'a> {
+  pub fn new(code: &'a [u8], calldata: &'a [u8]) -> Self {
+    Vm {
+      code,
+      calldata,
+      context: Context::default(),
+      jump_dests: jump_dests(code),
+      pc: 0,
+      stack: Vec::new(),
+      memory: Vec::new(),
+      storage: BTreeMap::new(),
+      logs: Vec::new(),
+    }
+  }
+
+  /// Sets the environment the code runs in.
+  pub fn with_context(mut self, context: Context) -> Self {
+    self.context = context;
+    self
+  }
+
+  /// Runs the code from the start until it halts.
+  pub fn run(mut self) -> Result<Execution, VmError> {
+    for _ in 0..STEP_LIMIT {
+      if let Some((halt, return_data)) = self.step()? {
+        if halt == Halt::Revert {
+          self.storage.clear();
+          self.logs.clear();
+        }
+
+        return Ok(Execution {
+          halt,
+          stack: self.stack,
+          return_data,
+          storage: self.storage,
+          logs: self.logs,
+        });
+      }
+    }
+
+    Err(VmError::StepLimit)
+  }
+
+  /// Executes one instruction, returning how the program halted if it did.
+  fn step(&mut self) -> Result<Option<(Halt, Vec<u8>)>, VmError> {
+    let opcode = match self.code.get(self.pc) {
+      Some(byte) => Opcode(*byte),
+      None => return Ok(Some((Halt::Stop, Vec::new()))),
+    };
+    let pc = self.pc;
+    self.pc += 1;
+
+    if let Some(info) = opcode.info() {
+      if self.stack.len() < info.inputs {
+        return Err(VmError::StackUnderflow { pc });
+      }
+
+      let operands = self.stack.iter().rev().take(info.inputs).copied().collect::<Vec<U256>>();
+      if let Some(result) = eval(opcode, &operands) {
+        self.stack.truncate(self.stack.len() - info.inputs);
+        self.stack.push(result);
+        return Ok(None);
+      }
+    }
+
+    if let Some(value) = self.context.get(opcode) {
+      self.push(value)?;
+      return Ok(None);
+    }
+
+    match opcode {
+      Opcode::STOP => return Ok(Some((Halt::Stop, Vec::new()))),
+      Opcode::KECCAK256 => {
+        let (offset, size) = (self.pop()?, self.pop()?);
+        let range = self.memory_range(pc, offset, size)?;
+        let hash = keccak256(&self.memory[range]);
+        self.push(U256::from_be_bytes(&hash))?;
+      }
+      Opcode::BALANCE => {
+        let address = self.pop()?;
+        let balance = if address == self.context.address {
+          self.context.balance
+        } else {
+          U256::ZERO
+        };
+        self.push(balance)?;
+      }
+      Opcode::CALLDATALOAD => {
+        let offset = self.pop()?;
+        let word = copy_padded(self.calldata, offset, 32);
+        self.push(U256::from_be_bytes(&word))?;
+      }
+      Opcode::CALLDATASIZE => self.push(U256::from(self.calldata.len()))?,
+      Opcode::CALLDATACOPY => self.copy_to_memory(pc, self.calldata)?,
+      Opcode::CODESIZE => self.push(U256::from(self.code.len()))?,
+      Opcode::CODECOPY => self.copy_to_memory(pc, self.code)?,
+      Opcode::POP => {
+        self.pop()?;
+      }
+      Opcode::MLOAD => {
+        let offset = self.pop()?;
+        let range = self.memory_range(pc, offset, U256::from(32u32))?;
+        let word = U256::from_be_bytes(&self.memory[range]);
+        self.push(word)?;
+      }
+      Opcode::MSTORE => {
+        let (offset, value) = (self.pop()?, self.pop()?);
+        let range = self.memory_range(pc, offset, U256::from(32u32))?;
+        self.memory[range].copy_from_slice(&value.to_be_bytes());
+      }
+      Opcode::MSTORE8 => {
+        let (offset, value) = (self.pop()?, self.pop()?);
+        let range = self.memory_range(pc, offset, U256::ONE)?;
+        self.memory[range.start] = value.low_u64() as u8;
+      }
+      Opcode::SLOAD => {
+        let key = self.pop()?;
+        let value = self.storage.get(&key).copied().unwrap_or_default();
+        self.push(value)?;
+      }
+      Opcode::SSTORE => {
+        let (key, value) = (self.pop()?, self.pop()?);
+        if value.is_zero() {
+          self.storage.remove(&key);
+        } else {
+          self.storage.insert(key, value);
+        }
+      }
+      Opcode::JUMP => {
+        let target = self.pop()?;
+        self.jump(pc, target)?;
+      }
+      Opcode::JUMPI => {
+        let (target, condition) = (self.pop()?, self.pop()?);
+        if !condition.is_zero() {
+          self.jump(pc, target)?;
+        }
+      }
+      Opcode::PC => self.push(U256::from(pc))?,
+      Opcode::MSIZE => self.push(U256::from(self.memory.len()))?,
+      Opcode::JUMPDEST => {}
+      Opcode::PUSH0 => self.push(U256::ZERO)?,
+      Opcode(byte) if (Opcode::push(1).0..=Opcode::PUSH32.0).contains(&byte) => {
+        let size = opcode.immediate_size();
+        let data = copy_padded(self.code, U256::from(self.pc), size);
+        self.pc += size;
+        self.push(U256::from_be_bytes(&data))?;
+      }
+      Opcode(byte) if (Opcode::DUP1.0..=Opcode::DUP16.0).contains(&byte) => {
+        let depth = (byte - Opcode::DUP1.0) as usize + 1;
+        let value = *self.peek(pc, depth)?;
+        self.push(value)?;
+      }
+      Opcode(byte) if (Opcode::SWAP1.0..=Opcode::SWAP16.0).contains(&byte) => {
+        let depth = (byte - Opcode::SWAP1.0) as usize + 2;
+        self.peek(pc, depth)?;
+        let top = self.stack.len() - 1;
+        self.stack.swap(top, top + 1 - depth);
+      }
+      Opcode(byte) if (Opcode::LOG0.0..=Opcode::log(4).0).contains(&byte) => {
+        let (offset, size) = (self.pop()?, self.pop()?);
+        let topics = (Opcode::LOG0.0..byte)
+          .map(|_| self.pop())
+          .collect::<Result<Vec<U256>, VmError>>()?;
+        let range = self.memory_range(pc, offset, size)?;
+        let data = self.memory[range].to_vec();
+
+        self.logs.push(Log { topics, data });
+      }
+      Opcode::RETURN | Opcode::REVERT => {
+        let (offset, size) = (self.pop()?, self.pop()?);
+        let range = self.memory_range(pc, offset, size)?;
+        let halt = if opcode == Opcode::RETURN {
+          Halt::Return
+        } else {
+          Halt::Revert
+        };
+
+        return Ok(Some((halt, self.memory[range].to_vec())));
+      }
+      Opcode::INVALID => return Err(VmError::InvalidOpcode { pc }),
+      Opcode(byte) => return Err(VmError::Unsupported { pc, opcode: byte }),
+    }
+
+    Ok(None)
+  }
+
+  fn pop(&mut self) -> Result<U256, VmError> {
+    self.stack.pop().ok_or(VmError::StackUnderflow { pc: self.pc - 1 })
+  }
+
+  fn push(&mut self, value: U256) -> Result<(), VmError> {
+    if self.stack.len() >= STACK_LIMIT {
+      return Err(VmError::StackOverflow { pc: self.pc - 1 });
+    }
+
+    self.stack.push(value);
+    Ok(())
+  }
+
+  /// The item `depth` places from the top, where the top is at depth 1.
+  fn peek(&self, pc: usize, depth: usize) -> Result<&U256, VmError> {
+    self
+      .stack
+      .len()
+      .checked_sub(depth)
+      .map(|i| &self.stack[i])
+      .ok_or(VmError::StackUnderflow { pc })
+  }
+
+  fn jump(&mut self, pc: usize, target: U256) -> Result<(), VmError> {
+    match target.to_usize() {
+      Some(dest) if self.jump_dests.get(dest) == Some(&true) => {
+        self.pc = dest;
+        Ok(())
+      }
+      _ => Err(VmError::InvalidJump { pc, target }),
+    }
+  }
+
+  /// Expands memory to cover `size` bytes at `offset` and returns their
+  /// range. Zero-sized accesses never expand memory.
+  fn memory_range(&mut self, pc: usize, offset: U256, size: U256) -> Result<Range<usize>, VmError> {
+    if size.is_zero() {
+      return Ok(0..0);
+    }
+
+    let (start, size) = match (offset.to_usize(), size.to_usize()) {
+      (Some(start), Some(size)) => (start, size),
+      _ => return Err(VmError::MemoryLimit { pc }),
+    };
+    let end = start
+      .checked_add(size)
+      .filter(|end| *end <= MEMORY_LIMIT)
+      .ok_or(VmError::MemoryLimit { pc })?;
+
+    if end > self.memory.len() {
+      self.memory.resize(end.div_ceil(32) * 32, 0);
+    }
+
+    Ok(start..end)
+  }
+
+  /// CALLDATACOPY and CODECOPY: copies from `source` into memory, padding
+  /// with zeroes past its end.
+  fn copy_to_memory(&mut self, pc: usize, source: &[u8]) -> Result<(), VmError> {
+    let (dest, offset, size) = (self.pop()?, self.pop()?, self.pop()?);
+    let range = self.memory_range(pc, dest, size)?;
+    let data = copy_padded(source, offset, range.len());
+    self.memory[range].copy_from_slice(&data);
+
+    Ok(())
+  }
+}
+
+/// Evaluates an opcode whose result depends only on its operands, given in
+/// stack order with the top first. Returns `None` for any other opcode, or
+/// if too few operands are given.
+pub fn eval(opcode: Opcode, operands: &[U256]) -> Option<U256> {
+  let arg = |i: usize| operands.get(i).copied();
+
+  let result = match opcode {
+    Opcode::ADD => arg(0)?.wrapping_add(arg(1)?),
+    Opcode::MUL => arg(0)?.wrapping_mul(arg(1)?),
+    Opcode::SUB => arg(0)?.wrapping_sub(arg(1)?),
+    Opcode::DIV => arg(0)?.div_rem(arg(1)?).map_or(U256::ZERO, |(q, _)| q),
+    Opcode::SDIV => signed_div(arg(0)?, arg(1)?),
+    Opcode::MOD => arg(0)?.div_rem(arg(1)?).map_or(U256::ZERO, |(_, r)| r),
+    Opcode::SMOD => signed_mod(arg(0)?, arg(1)?),
+    Opcode::ADDMOD => arg(0)?.add_mod(arg(1)?, arg(2)?).unwrap_or(U256::ZERO),
+    Opcode::MULMOD => arg(0)?.mul_mod(arg(1)?, arg(2)?).unwrap_or(U256::ZERO),
+    Opcode::EXP => arg(0)?.wrapping_pow(arg(1)?),
+    Opcode::SIGNEXTEND => sign_extend(arg(0)?, arg(1)?),
+    Opcode::LT => U256::from(arg(0)? < arg(1)?),
+    Opcode::GT => U256::from(arg(0)? > arg(1)?),
+    Opcode::SLT => U256::from(signed_lt(arg(0)?, arg(1)?)),
+    Opcode::SGT => U256::from(signed_lt(arg(1)?, arg(0)?)),
+    Opcode::EQ => U256::from(arg(0)? == arg(1)?),
+    Opcode::ISZERO => U256::from(arg(0)?.is_zero()),
+    Opcode::AND => arg(0)? & arg(1)?,
+    Opcode::OR => arg(0)? | arg(1)?,
+    Opcode::XOR => arg(0)? ^ arg(1)?,
+    Opcode::NOT => !arg(0)?,
+    Opcode::BYTE => match arg(0)?.to_usize() {
+      Some(i) if i < 32 => U256::from(arg(1)?.to_be_bytes()[i] as u64),
+      _ => U256::ZERO,
+    },
+    Opcode::SHL => arg(1)? << shift_amount(arg(0)?),
+    Opcode::SHR => arg(1)? >> shift_amount(arg(0)?),
+    Opcode::SAR => {
+      let (shift, value) = (shift_amount(arg(0)?), arg(1)?);
+
+      if value.is_negative() {
+        !(!value >> shift)
+      } else {
+        value >> shift
+      }
+    }
+    _ => return None,
+  };
+
+  Some(result)
+}
+
+/// Marks every offset in `code` that holds a JUMPDEST instruction, skipping
+/// over push data.
+fn jump_dests(code: &[u8]) -> Vec<bool> {
+  let mut dests = vec![false; code.len()];
+  let mut pc = 0;
+
+  while pc < code.len() {
+    let opcode = Opcode(code[pc]);
+    dests[pc] = opcode == Opcode::JUMPDEST;
+    pc += 1 + opcode.immediate_size();
+  }
+
+  dests
+}
+
+/// `size` bytes of `source` starting at `offset`, zero-padded past the end.
+fn copy_padded(source: &[u8], offset: U256, size: usize) -> Vec<u8> {
+  let mut data = vec![0; size];
+
+  if let Some(start) = offset.to_usize().filter(|start| *start < source.len()) {
+    let available = (source.len() - start).min(size);
+    data[..available].copy_from_slice(&source[start..start + available]);
+  }
+
+  data
+}
+
+fn shift_amount(shift: U256) -> usize {
+  shift.to_usize().unwrap_or(usize::MAX)
+}
+
+fn abs(value: U256) -> U256 {
+  if value.is_negative() {
+    value.wrapping_neg()
+  } else {
+    value
+  }
+}
+
+fn signed_lt(a: U256, b: U256) -> bool {
+  match (a.is_negative(), b.is_negative()) {
+    (true, false) => true,
+    (false, true) => false,
+    _ => a < b,
+  }
+}
+
+fn signed_div(a: U256, b: U256) -> U256 {
+  match abs(a).div_rem(abs(b)) {
+    Some((quotient, _)) if a.is_negative() != b.is_negative() => quotient.wrapping_neg(),
+    Some((quotient, _)) => quotient,
+    None => U256::ZERO,
+  }
+}
+
+/// The remainder takes the sign of the dividend.
+fn signed_mod(a: U256, b: U256) -> U256 {
+  match abs(a).div_rem(abs(b)) {
+    Some((_, remainder)) if a.is_negative() => remainder.wrapping_neg(),
+    Some((_, remainder)) => remainder,
+    None => U256::ZERO,
+  }
+}
+
+/// Extends the sign bit of the `byte`th lowest byte of `value`.
+fn sign_extend(byte: U256, value: U256) -> U256 {
+  match byte.to_usize() {
+    Some(byte) if byte < 31 => {
+      let bit = byte * 8 + 7;
+      let mask = (U256::ONE << (bit + 1)).wrapping_sub(U256::ONE);
+
+      if value.bit(bit) {
+        value | !mask
+      } else {
+        value & mask
+      }
+    }
+    _ => value,
+  }
+}
+
+impl fmt::Display for Halt {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Halt::Stop => write!(f, "stop"),
+      Halt::Return => write!(f, "return"),
+      Halt::Revert => write!(f, "revert"),
+    }
+  }
+}
+
+impl fmt::Display for Execution {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "halted: {}", self.halt)?;
+
+    writeln!(f, "stack ({} items, top first):", self.stack.len())?;
+    for (i, value) in self.stack.iter().rev().enumerate() {
+      writeln!(f, "  {:>4}: {:#x}", i, value)?;
+    }
+
+    writeln!(f, "return data: 0x{}", to_hex(&self.return_data))?;
+
+    writeln!(f, "storage ({} slots):", self.storage.len())?;
+    for (key, value) in &self.storage {
+      writeln!(f, "  {:#x}: {:#x}", key, value)?;
+    }
+
+    writeln!(f, "logs ({} entries):", self.logs.len())?;
+    for log in &self.logs {
+      let topics = log.topics.iter().map(|topic| format!("{:#x}", topic));
+      writeln!(f, "  [{}] 0x{}", topics.collect::<Vec<String>>().join(", "), to_hex(&log.data))?;
+    }
+
+    Ok(())
+  }
+}
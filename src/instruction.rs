@@ -0,0 +1,24 @@
+use crate::opcode::Opcode;
+use crate::uint::U256;
+
+/// A jump target, resolved to a byte offset by the assembler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label(pub usize);
+
+/// One unit of generated code, before labels have been resolved to offsets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+  /// An opcode without immediate data.
+  Op(Opcode),
+  /// Pushes a constant using the narrowest PUSH that fits it.
+  Push(U256),
+  /// Marks a jump target; assembles to JUMPDEST.
+  Label(Label),
+  /// Pushes the byte offset of a label.
+  PushLabel(Label),
+  /// Bytes copied into the output as they are, from inline assembly.
+  Raw(Vec<u8>),
+  /// Bytes that are never executed, such as the code of a sub-program. The
+  /// label resolves to the offset of the first byte.
+  Data(Label, Vec<u8>),
+}
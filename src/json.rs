@@ -0,0 +1,111 @@
+use std::fmt;
+
+/// A minimal JSON value, only as much as the compiler needs to write its
+/// artifacts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+  Num(u64),
+  Bool(bool),
+  Str(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+
+impl Json {
+  pub fn str(s: impl Into<String>) -> Json {
+    Json::Str(s.into())
+  }
+
+  pub fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+      fields
+        .into_iter()
+        .map(|(key, value)| (key.to_owned(), value))
+        .collect(),
+    )
+  }
+
+  /// Serializes the value with two-space indentation.
+  pub fn pretty(&self) -> String {
+    let mut out = String::new();
+    self.write_pretty(&mut out, 0);
+    out
+  }
+
+  fn write_pretty(&self, out: &mut String, indent: usize) {
+    let pad = "  ".repeat(indent + 1);
+
+    match self {
+      Json::Array(items) if !items.is_empty() => {
+        out.push_str("[\n");
+        for (i, item) in items.iter().enumerate() {
+          out.push_str(&pad);
+          item.write_pretty(out, indent + 1);
+          out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+        }
+        out.push_str(&"  ".repeat(indent));
+        out.push(']');
+      }
+      Json::Object(fields) if !fields.is_empty() => {
+        out.push_str("{\n");
+        for (i, (key, value)) in fields.iter().enumerate() {
+          out.push_str(&format!("{}{}: ", pad, escape(key)));
+          value.write_pretty(out, indent + 1);
+          out.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+        }
+        out.push_str(&"  ".repeat(indent));
+        out.push('}');
+      }
+      _ => out.push_str(&self.to_string()),
+    }
+  }
+}
+
+fn escape(s: &str) -> String {
+  let mut out = String::from("\"");
+
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+
+  out.push('"');
+  out
+}
+
+impl fmt::Display for Json {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Json::Num(n) => write!(f, "{}", n),
+      Json::Bool(b) => write!(f, "{}", b),
+      Json::Str(s) => write!(f, "{}", escape(s)),
+      Json::Array(items) => {
+        write!(f, "[")?;
+        for (i, item) in items.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write!(f, "{}", item)?;
+        }
+        write!(f, "]")
+      }
+      Json::Object(fields) => {
+        write!(f, "{{")?;
+        for (i, (key, value)) in fields.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write!(f, "{}:{}", escape(key), value)?;
+        }
+        write!(f, "}}")
+      }
+    }
+  }
+}
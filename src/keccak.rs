@@ -0,0 +1,106 @@
+/// Bytes absorbed per permutation: 1600 bits of state less 512 of capacity.
+const RATE: usize = 136;
+
+const ROUND_CONSTANTS: [u64; 24] = [
+  0x0000_0000_0000_0001,
+  0x0000_0000_0000_8082,
+  0x8000_0000_0000_808a,
+  0x8000_0000_8000_8000,
+  0x0000_0000_0000_808b,
+  0x0000_0000_8000_0001,
+  0x8000_0000_8000_8081,
+  0x8000_0000_0000_8009,
+  0x0000_0000_0000_008a,
+  0x0000_0000_0000_0088,
+  0x0000_0000_8000_8009,
+  0x0000_0000_8000_000a,
+  0x0000_0000_8000_808b,
+  0x8000_0000_0000_008b,
+  0x8000_0000_0000_8089,
+  0x8000_0000_0000_8003,
+  0x8000_0000_0000_8002,
+  0x8000_0000_0000_0080,
+  0x0000_0000_0000_800a,
+  0x8000_0000_8000_000a,
+  0x8000_0000_8000_8081,
+  0x8000_0000_0000_8080,
+  0x0000_0000_8000_0001,
+  0x8000_0000_8000_8008,
+];
+
+/// Rotation of each lane visited by the combined rho and pi steps.
+const ROTATIONS: [u32; 24] = [
+  1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+];
+
+/// The order in which the rho and pi steps visit lanes, starting from lane 1.
+const LANES: [usize; 24] = [
+  10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+];
+
+/// The Keccak-256 hash of `data`, as used by the EVM. This is the original
+/// Keccak padding, not that of the final SHA-3 standard.
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+  let mut padded = data.to_vec();
+  padded.push(0x01);
+  padded.resize(padded.len().div_ceil(RATE) * RATE, 0);
+  *padded.last_mut().unwrap() |= 0x80;
+
+  let mut state = [0u64; 25];
+
+  for block in padded.chunks(RATE) {
+    for (lane, bytes) in state.iter_mut().zip(block.chunks(8)) {
+      *lane ^= u64::from_le_bytes(bytes.try_into().unwrap());
+    }
+    permute(&mut state);
+  }
+
+  let mut hash = [0; 32];
+
+  for (bytes, lane) in hash.chunks_mut(8).zip(state.iter()) {
+    bytes.copy_from_slice(&lane.to_le_bytes());
+  }
+
+  hash
+}
+
+/// Keccak-f[1600]. Lane `x + 5 * y` of `state` holds column `x` of row `y`.
+fn permute(state: &mut [u64; 25]) {
+  for round_constant in ROUND_CONSTANTS {
+    // Theta: mix each column's parity into its neighbours.
+    let mut parity = [0u64; 5];
+
+    for (x, column) in parity.iter_mut().enumerate() {
+      *column = (0..5).fold(0, |acc, y| acc ^ state[x + 5 * y]);
+    }
+
+    for x in 0..5 {
+      let mix = parity[(x + 4) % 5] ^ parity[(x + 1) % 5].rotate_left(1);
+
+      for y in 0..5 {
+        state[x + 5 * y] ^= mix;
+      }
+    }
+
+    // Rho and pi: rotate every lane and move it to its new position.
+    let mut carried = state[1];
+
+    for (lane, rotation) in LANES.iter().zip(ROTATIONS.iter()) {
+      let next = state[*lane];
+      state[*lane] = carried.rotate_left(*rotation);
+      carried = next;
+    }
+
+    // Chi: the only non-linear step, applied row by row.
+    for row in state.chunks_mut(5) {
+      let copy = [row[0], row[1], row[2], row[3], row[4]];
+
+      for (x, lane) in row.iter_mut().enumerate() {
+        *lane = copy[x] ^ (!copy[(x + 1) % 5] & copy[(x + 2) % 5]);
+      }
+    }
+
+    // Iota.
+    state[0] ^= round_constant;
+  }
+}
@@ -1,4 +1,6 @@
+use crate::diagnostic::Span;
 use crate::token::{Token, TokenType};
+use crate::uint::U256;
 use regex::Regex;
 use std::iter::Peekable;
 use std::str::Chars;
@@ -7,6 +9,8 @@ pub struct Lexer<'a> {
   position: Peekable<Chars<'a>>,
   row: u32,
   col: u32,
+  offset: usize,
+  start: Span,
 }
 
 impl<'a> Lexer<'a> {
@@ -15,38 +19,42 @@ impl<'a> Lexer<'a> {
       position: input.chars().peekable(),
       row: 1,
       col: 0,
+      offset: 0,
+      start: Span::default(),
     }
   }
 
+  // Always yields a token, ending with EOF, so this is not an `Iterator`.
+  #[allow(clippy::should_implement_trait)]
   pub fn next(&mut self) -> Token {
-    let character = self.position.next();
-    self.col += 1;
+    self.start = Span::new(self.offset, self.offset, self.row, self.col + 1);
+    let character = self.bump();
 
     if let Some(c) = character {
       match c {
-        '(' => Token::new(TokenType::LPAREN, self.row, self.col),
-        ')' => Token::new(TokenType::RPAREN, self.row, self.col),
-        '{' => Token::new(TokenType::LBRACE, self.row, self.col),
-        '}' => Token::new(TokenType::RBRACE, self.row, self.col),
-        '[' => Token::new(TokenType::LBRACKET, self.row, self.col),
-        ']' => Token::new(TokenType::RBRACKET, self.row, self.col),
-        '+' => Token::new(TokenType::ADD, self.row, self.col),
-        '-' => Token::new(TokenType::SUB, self.row, self.col),
-        '*' => Token::new(TokenType::MUL, self.row, self.col),
-        '/' => Token::new(TokenType::DIV, self.row, self.col),
-        '%' => Token::new(TokenType::MOD, self.row, self.col),
-        '&' => Token::new(TokenType::BAND, self.row, self.col),
-        '|' => Token::new(TokenType::BOR, self.row, self.col),
-        '^' => Token::new(TokenType::BXOR, self.row, self.col),
-        '~' => Token::new(TokenType::BNOT, self.row, self.col),
-        '=' => Token::new(TokenType::EQ, self.row, self.col),
+        '(' => self.token(TokenType::LPAREN),
+        ')' => self.token(TokenType::RPAREN),
+        '{' => self.token(TokenType::LBRACE),
+        '}' => self.token(TokenType::RBRACE),
+        '[' => self.token(TokenType::LBRACKET),
+        ']' => self.token(TokenType::RBRACKET),
+        '+' => self.token(TokenType::ADD),
+        '-' => self.token(TokenType::SUB),
+        '*' => self.token(TokenType::MUL),
+        '/' => self.token(TokenType::DIV),
+        '%' => self.token(TokenType::MOD),
+        '&' => self.token(TokenType::BAND),
+        '|' => self.token(TokenType::BOR),
+        '^' => self.token(TokenType::BXOR),
+        '~' => self.token(TokenType::BNOT),
+        '=' => self.token(TokenType::EQ),
         ';' => {
           self.next_line();
           self.next()
         }
         '\'' | '"' => self.lex_string(c),
         '>' | '<' | 'S' | '!' | '@' => self.lex_multi_char(c),
-        ' ' => self.next(),
+        ' ' | '\t' | '\r' => self.next(),
         '\n' => {
           self.row += 1;
           self.col = 0;
@@ -56,17 +64,39 @@ impl<'a> Lexer<'a> {
         c if c.is_ascii_alphabetic() => {
           let word = self.read_word(c);
           match word.as_str() {
-            "def" => Token::new(TokenType::DEF, self.row, self.col),
-            _ => Token::new(TokenType::IDENT(word), self.row, self.col)
+            "def" => self.token(TokenType::DEF),
+            _ => self.token(TokenType::IDENT(word)),
           }
         }
-        _ => Token::new(TokenType::EOF, self.row, self.col),
+        _ => self.token(TokenType::INVALID(format!("Unexpected character `{}`", c))),
       }
     } else {
-      Token::new(TokenType::EOF, self.row, self.col)
+      self.token(TokenType::EOF)
     }
   }
 
+  /// Builds a token spanning from the start of the current lexeme to the
+  /// current position.
+  fn token(&self, token_type: TokenType) -> Token {
+    let span = Span {
+      end: self.offset,
+      ..self.start
+    };
+
+    Token::new(token_type, span)
+  }
+
+  fn bump(&mut self) -> Option<char> {
+    let character = self.position.next();
+
+    if let Some(c) = character {
+      self.col += 1;
+      self.offset += c.len_utf8();
+    }
+
+    character
+  }
+
   fn lex_string(&mut self, curr_char: char) -> Token {
     if curr_char == '\'' {
       let mut string = self.read_word(curr_char);
@@ -74,42 +104,44 @@ impl<'a> Lexer<'a> {
 
       if single_quote_pattern.is_match(&string) {
         string = String::from(string.trim_start_matches("'"));
-        return Token::new(TokenType::STR(string), self.row, self.col);
+        self.token(TokenType::STR(string))
       } else {
-        return Token::new(TokenType::INVALID, self.row, self.col);
+        self.token(TokenType::INVALID(format!("Invalid quoted name `{}`", string)))
       }
     } else {
-      let string = String::from(
-        self
-          .read_double_quote_string(curr_char)
-          .trim_start_matches("\"")
-          .trim_end_matches("\""),
-      );
-
-      return Token::new(TokenType::STR(string), self.row, self.col);
+      let string = self.read_double_quote_string(curr_char);
+
+      if string.len() < 2 || !string.ends_with('"') {
+        return self.token(TokenType::INVALID(String::from("Unterminated string")));
+      }
+
+      self.token(TokenType::STR(String::from(
+        string.trim_start_matches('"').trim_end_matches('"'),
+      )))
     }
   }
 
   fn lex_int(&mut self, curr_char: char) -> Token {
     let word = self.read_word(curr_char);
+    let digits = word.replace('_', "");
 
-    let int = word.parse::<u32>();
+    let (radix, digits) = match digits.get(..2) {
+      Some("0x") | Some("0X") => (16, &digits[2..]),
+      Some("0b") | Some("0B") => (2, &digits[2..]),
+      Some("0o") | Some("0O") => (8, &digits[2..]),
+      _ => (10, &digits[..]),
+    };
 
-    if let Ok(i) = int {
-      Token::new(TokenType::INT(i), self.row, self.col)
-    } else {
-      if word.starts_with("0x") {
-        let stripped_string = word.trim_start_matches("0x");
-        let hex_int = u32::from_str_radix(stripped_string, 16);
-
-        if let Ok(i) = hex_int {
-          Token::new(TokenType::INT(i), self.row, self.col)
-        } else {
-          Token::new(TokenType::INVALID, self.row, self.col)
-        }
-      } else {
-        Token::new(TokenType::INVALID, self.row, self.col)
-      }
+    if digits.is_empty() || word.ends_with('_') || !digits.chars().all(|c| c.is_digit(radix)) {
+      return self.token(TokenType::INVALID(format!("Invalid integer literal `{}`", word)));
+    }
+
+    match U256::from_str_radix(digits, radix) {
+      Some(i) => self.token(TokenType::INT(i)),
+      None => self.token(TokenType::INVALID(format!(
+        "Integer literal `{}` does not fit in 256 bits",
+        word
+      ))),
     }
   }
 
@@ -117,18 +149,23 @@ impl<'a> Lexer<'a> {
     let word = self.read_word(curr_char);
 
     match word.as_str() {
-      ">=" => Token::new(TokenType::GTOE, self.row, self.col),
-      "<=" => Token::new(TokenType::LTOE, self.row, self.col),
-      "<" => Token::new(TokenType::LT, self.row, self.col),
-      ">" => Token::new(TokenType::GT, self.row, self.col),
-      "S>" => Token::new(TokenType::SGT, self.row, self.col),
-      "S<" => Token::new(TokenType::SLT, self.row, self.col),
-      "S>=" => Token::new(TokenType::SGTOE, self.row, self.col),
-      "S<=" => Token::new(TokenType::SLTOE, self.row, self.col),
-      "!=" => Token::new(TokenType::NEQ, self.row, self.col),
-      "@" => Token::new(TokenType::AT, self.row, self.col),
-      "@@" => Token::new(TokenType::DAT, self.row, self.col),
-      _ => Token::new(TokenType::EOF, self.row, self.col),
+      ">=" => self.token(TokenType::GTOE),
+      "<=" => self.token(TokenType::LTOE),
+      "<" => self.token(TokenType::LT),
+      ">" => self.token(TokenType::GT),
+      "S>" => self.token(TokenType::SGT),
+      "S<" => self.token(TokenType::SLT),
+      "S>=" => self.token(TokenType::SGTOE),
+      "S<=" => self.token(TokenType::SLTOE),
+      "S/" => self.token(TokenType::SDIV),
+      "S%" => self.token(TokenType::SMOD),
+      "!=" => self.token(TokenType::NEQ),
+      "@" => self.token(TokenType::AT),
+      "@@" => self.token(TokenType::DAT),
+      // Any other word that starts with `S`, such as SSTORE or SWAP1, is an
+      // identifier, as it is after any other letter.
+      _ if curr_char == 'S' => self.token(TokenType::IDENT(word)),
+      _ => self.token(TokenType::INVALID(format!("Unknown operator `{}`", word))),
     }
   }
 
@@ -139,20 +176,18 @@ impl<'a> Lexer<'a> {
 
     while character.is_some() && character != Some(&'"') {
       word.push(*character.unwrap());
-      if character == Some(&' ') {
+
+      if self.bump() == Some('\n') {
         self.row += 1;
         self.col = 0;
-      } else {
-        self.col += 1;
       }
 
-      self.position.next();
       character = self.position.peek();
     }
 
     if character == Some(&'"') {
-      self.position.next();
-      self.col += 1;
+      word.push('"');
+      self.bump();
     }
 
     word
@@ -165,22 +200,19 @@ impl<'a> Lexer<'a> {
 
     while character.is_some()
       && character != Some(&' ')
+      && character != Some(&'\t')
+      && character != Some(&'\r')
       && character != Some(&'(')
       && character != Some(&')')
       && character != Some(&'{')
       && character != Some(&'}')
+      && character != Some(&'[')
+      && character != Some(&']')
       && character != Some(&'\n')
     {
       word.push(*character.unwrap());
 
-      if character == Some(&' ') {
-        self.row += 1;
-        self.col = 0;
-      } else {
-        self.col += 1;
-      }
-
-      self.position.next();
+      self.bump();
       character = self.position.peek();
     }
 
@@ -188,8 +220,8 @@ impl<'a> Lexer<'a> {
   }
 
   fn next_line(&mut self) {
-    while self.position.peek() != Some(&'\n') && self.position.peek() != None {
-      self.position.next();
+    while self.position.peek() != Some(&'\n') && self.position.peek().is_some() {
+      self.bump();
     }
   }
 }
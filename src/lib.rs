@@ -0,0 +1,25 @@
+// Diagnostics are large, but they are only built on the error path.
+#![allow(clippy::result_large_err)]
+
+pub mod abi;
+pub mod assembler;
+pub mod ast;
+pub mod builtin;
+pub mod compiler;
+pub mod diagnostic;
+pub mod disasm;
+pub mod evm;
+pub mod instruction;
+pub mod json;
+pub mod keccak;
+pub mod lexer;
+pub mod opcode;
+pub mod optimizer;
+pub mod output;
+pub mod parser;
+pub mod stack;
+pub mod token;
+pub mod uint;
+
+#[cfg(test)]
+mod tests;
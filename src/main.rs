@@ -1,23 +1,22 @@
-mod ast;
-mod compiler;
-mod lexer;
-mod parser;
-mod token;
-
-#[cfg(test)]
-mod tests;
-
-use crate::compiler::Compiler;
-use crate::lexer::Lexer;
-use crate::parser::Parser;
-use clap::{App, Arg};
-use std::fs::read_to_string;
+use blllc::abi;
+use blllc::compiler::Compiler;
+use blllc::diagnostic::Diagnostic;
+use blllc::disasm;
+use blllc::evm::{Halt, Vm};
+use blllc::lexer::Lexer;
+use blllc::optimizer::OptLevel;
+use blllc::output::{self, Format};
+use blllc::parser::Parser;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::fs::{read, read_to_string, write};
+use std::io::Write;
 use std::path::Path;
 
 fn main() {
     let matches = App::new("Brett's Lovely Little Language Compiler")
         .version("0.1.0")
         .author("Brett Kolodny <brettkolodny@gmail.com>")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("input")
                 .help("Input file")
@@ -25,27 +24,184 @@ fn main() {
                 .index(1)
                 .required(true),
         )
+        .arg(
+            Arg::with_name("output")
+                .help("Output format")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .possible_values(&Format::NAMES)
+                .default_value("hex"),
+        )
+        .arg(optimization_arg())
+        .arg(
+            Arg::with_name("out-file")
+                .help("Write the output to a file instead of stdout")
+                .long("out-file")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("abi")
+                .help("Also write the contract's ABI as JSON to a file")
+                .long("abi")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Compiles a program and executes it in the built-in EVM")
+                .arg(
+                    Arg::with_name("input")
+                        .help("Input file")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("calldata")
+                        .help("Call data as hex")
+                        .long("calldata")
+                        .value_name("HEX")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("bytecode")
+                        .help("Treat the input as hex or binary byte code instead of source")
+                        .long("bytecode"),
+                )
+                .arg(optimization_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("disasm")
+                .about("Prints annotated assembly for hex or binary byte code")
+                .arg(
+                    Arg::with_name("input")
+                        .help("Byte code file")
+                        .index(1)
+                        .required(true),
+                ),
+        )
         .get_matches();
 
-    if let Some(input) = matches.value_of("input") {
-        let path = Path::new(input);
-        let file_str = read_to_string(path).expect(&format!("Could not open file at {}", &input));
+    match matches.subcommand() {
+        ("run", Some(run_matches)) => run(run_matches),
+        ("disasm", Some(disasm_matches)) => {
+            let byte_code = read_bytecode_file(disasm_matches.value_of("input").unwrap());
+            print!("{}", disasm::render(&byte_code));
+        }
+        _ => build(&matches),
+    }
+}
+
+fn optimization_arg() -> Arg<'static, 'static> {
+    Arg::with_name("optimize")
+        .help("Optimization level")
+        .short("O")
+        .takes_value(true)
+        .possible_values(&OptLevel::NAMES)
+        .default_value("0")
+}
 
-        let lexer = Lexer::new(&file_str);
-        let mut parser = Parser::new(lexer);
+fn build(matches: &ArgMatches) {
+    let input = matches.value_of("input").unwrap();
+    let (byte_code, compiler) = compile_file(input, opt_level(matches));
 
-        match parser.parse() {
-            Ok(ast) => {
-                let mut compiler = Compiler::new(ast);
-                let byte_code = compiler.compile().expect("Compilation error");
-                println!("{}", byte_code);
-            }
-            Err(e) => {
-                eprintln!("{}", e);
+    let format = matches
+        .value_of("output")
+        .and_then(|format| format.parse().ok())
+        .unwrap_or(Format::Hex);
+    let rendered = output::render(format, &byte_code, compiler.source_map(), input);
+
+    let written = match matches.value_of("out-file") {
+        Some(out_file) => write(out_file, &rendered),
+        None => std::io::stdout().write_all(&rendered),
+    };
+
+    if let Err(e) = written {
+        eprintln!("Could not write output: {}", e);
+        std::process::exit(1);
+    }
+
+    if let Some(abi_file) = matches.value_of("abi") {
+        let description = abi::describe(compiler.interface()).pretty();
+
+        if let Err(e) = write(abi_file, format!("{}\n", description)) {
+            eprintln!("Could not write ABI: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run(matches: &ArgMatches) {
+    let input = matches.value_of("input").unwrap();
+
+    let byte_code = if matches.is_present("bytecode") {
+        read_bytecode_file(input)
+    } else {
+        compile_file(input, opt_level(matches)).0
+    };
+
+    let calldata = match matches.value_of("calldata") {
+        Some(hex) => output::from_hex(hex).unwrap_or_else(|| {
+            eprintln!("Invalid call data `{}`: expected hex bytes", hex);
+            std::process::exit(1);
+        }),
+        None => Vec::new(),
+    };
+
+    match Vm::new(&byte_code, &calldata).run() {
+        Ok(execution) => {
+            print!("{}", execution);
+
+            if execution.halt == Halt::Revert {
                 std::process::exit(1);
             }
         }
-    } else {
-        std::process::exit(1);
+        Err(e) => {
+            eprintln!("Execution failed: {}", e);
+            std::process::exit(1);
+        }
     }
 }
+
+fn read_bytecode_file(input: &str) -> Vec<u8> {
+    let contents = read(input).unwrap_or_else(|_| panic!("Could not open file at {}", &input));
+    output::read_bytecode(&contents)
+}
+
+fn opt_level(matches: &ArgMatches) -> OptLevel {
+    matches
+        .value_of("optimize")
+        .and_then(|level| level.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Compiles the file at `input`, printing any warnings, and returns the byte
+/// code along with the compiler for anything else it produced. Exits with
+/// the rendered diagnostic if compilation fails.
+fn compile_file(input: &str, level: OptLevel) -> (Vec<u8>, Compiler) {
+    let path = Path::new(input);
+    let file_str =
+        read_to_string(path).unwrap_or_else(|_| panic!("Could not open file at {}", &input));
+
+    let lexer = Lexer::new(&file_str);
+    let mut parser = Parser::new(lexer);
+
+    let ast = parser.parse().unwrap_or_else(|e| fail(&e, input, &file_str));
+    let mut compiler = Compiler::new(ast).with_optimization(level);
+    let byte_code = compiler.compile();
+
+    for warning in compiler.warnings() {
+        eprintln!("{}", warning.render(input, &file_str));
+    }
+
+    match byte_code {
+        Ok(byte_code) => (byte_code, compiler),
+        Err(e) => fail(&e, input, &file_str),
+    }
+}
+
+fn fail(diagnostic: &Diagnostic, input: &str, source: &str) -> ! {
+    eprintln!("{}", diagnostic.render(input, source));
+    std::process::exit(1);
+}
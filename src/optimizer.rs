@@ -0,0 +1,284 @@
+use crate::evm::eval;
+use crate::instruction::{Instruction, Label};
+use crate::opcode::Opcode;
+use crate::stack::TERMINATORS;
+use crate::uint::U256;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// How hard the optimizer works.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+  /// Code is emitted exactly as generated.
+  #[default]
+  O0,
+  /// Constant folding and peephole rules.
+  O1,
+  /// Also resolves constant jumps and removes code that can never run.
+  O2,
+}
+
+impl OptLevel {
+  pub const NAMES: [&'static str; 3] = ["0", "1", "2"];
+}
+
+impl FromStr for OptLevel {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "0" => Ok(OptLevel::O0),
+      "1" => Ok(OptLevel::O1),
+      "2" => Ok(OptLevel::O2),
+      _ => Err(format!("Unknown optimization level `{}`", s)),
+    }
+  }
+}
+
+/// Opcodes whose result is always 0 or 1.
+const BOOLEAN: [Opcode; 6] = [
+  Opcode::LT,
+  Opcode::GT,
+  Opcode::SLT,
+  Opcode::SGT,
+  Opcode::EQ,
+  Opcode::ISZERO,
+];
+
+/// Rewrites `code` into an equivalent, cheaper sequence.
+///
+/// Every label referenced by `code` must be defined in it, so unreferenced
+/// labels can be dropped. Inline assembly is left untouched, and no code is
+/// removed after it, since it may contain jump destinations of its own.
+pub fn optimize(code: Vec<Instruction>, level: OptLevel) -> Vec<Instruction> {
+  let notes = vec![(); code.len()];
+  optimize_annotated(code, notes, level).0
+}
+
+/// Like `optimize`, but also keeps `notes`, one per instruction, in step with
+/// the code. An instruction that replaces others takes the note of the first
+/// one it replaces.
+pub fn optimize_annotated<T: Copy>(
+  code: Vec<Instruction>,
+  notes: Vec<T>,
+  level: OptLevel,
+) -> (Vec<Instruction>, Vec<T>) {
+  let mut code = Annotated { code, notes };
+
+  if level == OptLevel::O0 {
+    return (code.code, code.notes);
+  }
+
+  loop {
+    let mut changed = peephole(&mut code);
+
+    if level >= OptLevel::O2 {
+      changed |= fold_jumps(&mut code);
+      changed |= remove_unreachable(&mut code);
+    }
+
+    if !changed {
+      return (code.code, code.notes);
+    }
+  }
+}
+
+/// Instructions with a note attached to each.
+struct Annotated<T> {
+  code: Vec<Instruction>,
+  notes: Vec<T>,
+}
+
+impl<T: Copy> Annotated<T> {
+  /// Replaces `length` instructions starting at `start`.
+  fn replace(&mut self, start: usize, length: usize, replacement: Vec<Instruction>) {
+    let note = self.notes[start];
+    let count = replacement.len();
+
+    self.code.splice(start..start + length, replacement);
+    self
+      .notes
+      .splice(start..start + length, std::iter::repeat_n(note, count));
+  }
+}
+
+/// Applies local rewrites until none match. Returns whether anything changed.
+fn peephole<T: Copy>(annotated: &mut Annotated<T>) -> bool {
+  let mut changed = false;
+  let mut i = 0;
+
+  while i < annotated.code.len() {
+    let code = &annotated.code;
+    let previous = i.checked_sub(1).map(|j| &code[j]);
+
+    if let Some((length, replacement)) = rewrite(&code[i..], previous) {
+      annotated.replace(i, length, replacement);
+      changed = true;
+      // Step back so the replacement can combine with what came before it.
+      i = i.saturating_sub(3);
+    } else {
+      i += 1;
+    }
+  }
+
+  changed
+}
+
+/// Matches a rewrite rule at the start of `code`, returning how many
+/// instructions it replaces and what with.
+fn rewrite(
+  code: &[Instruction],
+  previous: Option<&Instruction>,
+) -> Option<(usize, Vec<Instruction>)> {
+  use Instruction::{Op, Push, PushLabel};
+
+  match code {
+    // x ISZERO ISZERO is x when x is already 0 or 1, or is only used as a
+    // jump condition.
+    [Op(Opcode::ISZERO), Op(Opcode::ISZERO), PushLabel(_), Op(Opcode::JUMPI), ..] => {
+      Some((2, vec![]))
+    }
+    [Op(Opcode::ISZERO), Op(Opcode::ISZERO), ..]
+      if matches!(previous, Some(Op(opcode)) if BOOLEAN.contains(opcode)) =>
+    {
+      Some((2, vec![]))
+    }
+    [Op(Opcode::NOT), Op(Opcode::NOT), ..] => Some((2, vec![])),
+    // Identities: x + 0, x | 0, x ^ 0 and x * 1.
+    [Push(zero), Op(Opcode::ADD | Opcode::OR | Opcode::XOR), ..] if zero.is_zero() => {
+      Some((2, vec![]))
+    }
+    [Push(one), Op(Opcode::MUL), ..] if *one == U256::ONE => Some((2, vec![])),
+    [Push(_), Op(Opcode::POP), ..] => Some((2, vec![])),
+    _ => fold_constants(code),
+  }
+}
+
+/// Replaces a run of pushes and pure opcodes with the values it leaves. Takes
+/// the longest run whose values encode in no more bytes than the run itself,
+/// so that folding never makes the code larger.
+fn fold_constants(code: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+  let mut stack = Vec::new();
+  let mut size = 0;
+  let mut folded = None;
+
+  for (i, instruction) in code.iter().enumerate() {
+    let value = match instruction {
+      Instruction::Push(value) => *value,
+      Instruction::Op(opcode) => {
+        let inputs = match opcode.info() {
+          Some(info) if info.inputs <= stack.len() => info.inputs,
+          _ => break,
+        };
+        // The top of the stack is the first operand.
+        let mut operands = stack.split_off(stack.len() - inputs);
+        operands.reverse();
+
+        match eval(*opcode, &operands) {
+          Some(value) => value,
+          None => break,
+        }
+      }
+      _ => break,
+    };
+
+    size += match instruction {
+      Instruction::Push(value) => push_size(*value),
+      _ => 1,
+    };
+    stack.push(value);
+
+    if matches!(instruction, Instruction::Op(_))
+      && stack.iter().map(|value| push_size(*value)).sum::<usize>() <= size
+    {
+      folded = Some((i + 1, stack.iter().map(|value| Instruction::Push(*value)).collect()));
+    }
+  }
+
+  folded
+}
+
+/// The bytes a PUSH of `value` takes.
+fn push_size(value: U256) -> usize {
+  1 + value.to_be_bytes_trimmed().len()
+}
+
+/// Turns conditional jumps on constants into unconditional jumps or nothing,
+/// and drops jumps to the very next instruction.
+fn fold_jumps<T: Copy>(annotated: &mut Annotated<T>) -> bool {
+  use Instruction::{Op, Push, PushLabel};
+
+  let mut changed = false;
+  let mut i = 0;
+
+  while i < annotated.code.len() {
+    match &annotated.code[i..] {
+      [Push(condition), PushLabel(label), Op(Opcode::JUMPI), ..] => {
+        let replacement = if condition.is_zero() {
+          vec![]
+        } else {
+          vec![PushLabel(*label), Op(Opcode::JUMP)]
+        };
+        annotated.replace(i, 3, replacement);
+        changed = true;
+      }
+      [PushLabel(target), Op(Opcode::JUMP), Instruction::Label(next), ..] if target == next => {
+        annotated.replace(i, 2, vec![]);
+        changed = true;
+      }
+      _ => i += 1,
+    }
+  }
+
+  changed
+}
+
+/// Removes code that follows a terminator and is not a jump target, along
+/// with labels nothing jumps to.
+fn remove_unreachable<T: Copy>(annotated: &mut Annotated<T>) -> bool {
+  let referenced = annotated
+    .code
+    .iter()
+    .filter_map(|instruction| match instruction {
+      Instruction::PushLabel(label) => Some(*label),
+      _ => None,
+    })
+    .collect::<HashSet<Label>>();
+
+  let mut reachable = true;
+  let keep = annotated
+    .code
+    .iter()
+    .map(|instruction| {
+      match instruction {
+        Instruction::Label(label) if referenced.contains(label) => reachable = true,
+        Instruction::Label(_) => return false,
+        Instruction::Raw(_) => reachable = true,
+        // Data is only ever read, never reached.
+        Instruction::Data(..) => return true,
+        _ => {}
+      }
+
+      let keep = reachable;
+
+      if let Instruction::Op(opcode) = instruction {
+        if TERMINATORS.contains(opcode) {
+          reachable = false;
+        }
+      }
+
+      keep
+    })
+    .collect::<Vec<bool>>();
+
+  if keep.iter().all(|keep| *keep) {
+    return false;
+  }
+
+  let mut kept = keep.iter();
+  annotated.code.retain(|_| *kept.next().unwrap());
+  let mut kept = keep.iter();
+  annotated.notes.retain(|_| *kept.next().unwrap());
+
+  true
+}
@@ -0,0 +1,163 @@
+use crate::compiler::SourceMapping;
+use crate::disasm::disassemble;
+use crate::json::Json;
+use std::str::FromStr;
+
+/// The ways compiled byte code can be written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// Lowercase hex, as printed by earlier versions of the compiler.
+  Hex,
+  /// Lowercase hex with a leading `0x`.
+  PrefixedHex,
+  /// The raw bytes.
+  Bin,
+  /// A JSON artifact with the byte code, source map and compiler metadata.
+  Json,
+  /// The source map in Solidity's compressed `s:l:f:j` form.
+  SourceMap,
+}
+
+impl Format {
+  pub const NAMES: [&'static str; 5] = ["hex", "0x", "bin", "json", "srcmap"];
+}
+
+impl FromStr for Format {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "hex" => Ok(Format::Hex),
+      "0x" => Ok(Format::PrefixedHex),
+      "bin" => Ok(Format::Bin),
+      "json" => Ok(Format::Json),
+      "srcmap" => Ok(Format::SourceMap),
+      _ => Err(format!("Unknown output format `{}`", s)),
+    }
+  }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes a hex string, with or without a leading `0x`. Surrounding
+/// whitespace is ignored.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+  let hex = hex.trim();
+  let hex = hex.strip_prefix("0x").unwrap_or(hex);
+
+  if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+    return None;
+  }
+
+  (0..hex.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+    .collect()
+}
+
+/// Reads byte code written in either the hex or the binary format.
+pub fn read_bytecode(contents: &[u8]) -> Vec<u8> {
+  std::str::from_utf8(contents)
+    .ok()
+    .and_then(from_hex)
+    .unwrap_or_else(|| contents.to_vec())
+}
+
+/// Renders `byte_code` in the requested format. Text formats end with a
+/// newline, `Bin` is written as is.
+pub fn render(
+  format: Format,
+  byte_code: &[u8],
+  source_map: &[SourceMapping],
+  source_name: &str,
+) -> Vec<u8> {
+  match format {
+    Format::Hex => format!("{}\n", to_hex(byte_code)).into_bytes(),
+    Format::PrefixedHex => format!("0x{}\n", to_hex(byte_code)).into_bytes(),
+    Format::Bin => byte_code.to_vec(),
+    Format::Json => {
+      let artifact = artifact(byte_code, source_map, source_name);
+      format!("{}\n", artifact.pretty()).into_bytes()
+    }
+    Format::SourceMap => {
+      format!("{}\n", solidity_source_map(byte_code, source_map)).into_bytes()
+    }
+  }
+}
+
+/// Encodes `source_map` the way Solidity does: one `s:l:f:j` entry per
+/// instruction of `byte_code`, separated by `;`, where fields equal to the
+/// previous entry's are left empty and trailing empty fields are dropped.
+///
+/// `s` and `l` are the byte offset and length of the source range. All code
+/// comes from file 0, or -1 where no mapping covers it. There are no
+/// functions, so every jump is a plain `-`.
+pub fn solidity_source_map(byte_code: &[u8], source_map: &[SourceMapping]) -> String {
+  let mut previous = Vec::new();
+  let mut entries = Vec::new();
+
+  for line in disassemble(byte_code) {
+    let index =
+      source_map.partition_point(|mapping| mapping.offset + mapping.length <= line.offset);
+    let fields = match source_map.get(index) {
+      Some(mapping) if mapping.offset <= line.offset => vec![
+        mapping.span.start.to_string(),
+        (mapping.span.end - mapping.span.start).to_string(),
+        "0".to_owned(),
+        "-".to_owned(),
+      ],
+      _ => vec!["-1".to_owned(), "-1".to_owned(), "-1".to_owned(), "-".to_owned()],
+    };
+
+    let mut entry = fields
+      .iter()
+      .enumerate()
+      .map(|(i, field)| match previous.get(i) {
+        Some(previous) if previous == field => "",
+        _ => field.as_str(),
+      })
+      .collect::<Vec<&str>>();
+
+    while entry.last() == Some(&"") {
+      entry.pop();
+    }
+
+    entries.push(entry.join(":"));
+    previous = fields;
+  }
+
+  entries.join(";")
+}
+
+fn artifact(byte_code: &[u8], mappings: &[SourceMapping], source_name: &str) -> Json {
+  let source_map = mappings
+    .iter()
+    .map(|mapping| {
+      Json::object(vec![
+        ("offset", Json::Num(mapping.offset as u64)),
+        ("length", Json::Num(mapping.length as u64)),
+        ("start", Json::Num(mapping.span.start as u64)),
+        ("end", Json::Num(mapping.span.end as u64)),
+        ("line", Json::Num(mapping.span.row as u64)),
+        ("column", Json::Num(mapping.span.col as u64)),
+      ])
+    })
+    .collect();
+
+  Json::object(vec![
+    ("bytecode", Json::str(format!("0x{}", to_hex(byte_code)))),
+    ("sourceMap", Json::Array(source_map)),
+    ("srcmap", Json::str(solidity_source_map(byte_code, mappings))),
+    (
+      "metadata",
+      Json::object(vec![
+        ("compiler", Json::str(env!("CARGO_PKG_NAME"))),
+        ("version", Json::str(env!("CARGO_PKG_VERSION"))),
+        ("source", Json::str(source_name)),
+        ("size", Json::Num(byte_code.len() as u64)),
+      ]),
+    ),
+  ])
+}
@@ -1,12 +1,20 @@
+use crate::abi::{parse_types, AbiType, Event, Signature};
 use crate::ast::{Expression, Op};
+use crate::builtin::builtin;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::instruction::Instruction;
 use crate::lexer::Lexer;
+use crate::opcode::Opcode;
+use crate::output::from_hex;
 use crate::token::{Token, TokenType};
+use crate::uint::U256;
 use std::mem;
 
 pub struct Parser<'a> {
   lexer: Lexer<'a>,
   current_token: Token,
   peek_token: Token,
+  delimiters: Vec<Span>,
 }
 
 impl<'a> Parser<'a> {
@@ -18,6 +26,7 @@ impl<'a> Parser<'a> {
       lexer,
       current_token,
       peek_token,
+      delimiters: vec![],
     }
   }
 
@@ -26,7 +35,34 @@ impl<'a> Parser<'a> {
     self.peek_token = self.lexer.next();
   }
 
-  pub fn parse(&mut self) -> Result<Expression, String> {
+  fn error(&self, message: &str) -> Diagnostic {
+    let token = &self.current_token;
+
+    match &token.token_type {
+      TokenType::INVALID(reason) => Diagnostic::error("E0003", reason.clone(), token.span),
+      TokenType::EOF => self.unclosed(),
+      TokenType::RPAREN | TokenType::RBRACE => match self.delimiters.last() {
+        Some(open) => Diagnostic::error("E0002", "Mismatched closing delimiter", token.span)
+          .with_secondary(*open, "unclosed delimiter"),
+        None => Diagnostic::error("E0002", "Unexpected closing delimiter", token.span)
+          .with_label("no matching opening delimiter"),
+      },
+      _ => Diagnostic::error("E0001", message, token.span).with_label(format!("found {}", token)),
+    }
+  }
+
+  /// Reports reaching the end of the input while a delimiter is still open.
+  fn unclosed(&self) -> Diagnostic {
+    let eof = self.current_token.span;
+
+    match self.delimiters.last() {
+      Some(open) => Diagnostic::error("E0002", "This file contains an unclosed delimiter", eof)
+        .with_secondary(*open, "unclosed delimiter"),
+      None => Diagnostic::error("E0001", "Unexpected end of file", eof),
+    }
+  }
+
+  pub fn parse(&mut self) -> Result<Expression, Diagnostic> {
     let mut ast = Expression::new_program();
 
     let mut expressions: Vec<Expression> = vec![];
@@ -41,44 +77,115 @@ impl<'a> Parser<'a> {
     Ok(ast)
   }
 
-  fn parse_program(&mut self) -> Result<Expression, String> {
+  fn parse_program(&mut self) -> Result<Expression, Diagnostic> {
+    let span = self.current_token.span;
+
     match &self.current_token.token_type {
-      TokenType::LPAREN | TokenType::RPAREN => {
+      TokenType::LPAREN => {
+        self.delimiters.push(span);
         self.advance_tokens();
-        self.parse_program()
+        let mut expr = self.parse_list()?;
+        self.delimiters.pop();
+
+        expr.span = span.to(self.current_token.span);
+        Ok(expr)
       }
+      TokenType::INT(i) => Ok(Expression::new(Op::Num(*i), vec![], span)),
+      TokenType::IDENT(i) => match builtin(i) {
+        Some(opcode) => {
+          let expr = Expression::new(Op::Builtin(opcode), vec![], span);
+          Self::check_builtin_arity(i, opcode, expr)
+        }
+        None => Ok(Expression::new(Op::Ident(i.clone()), vec![], span)),
+      },
+      TokenType::LBRACKET => self.parse_store(),
+      TokenType::LBRACE => self.parse_block(),
+      _ => Err(self.error("Expected expression")),
+    }
+  }
+
+  fn parse_list(&mut self) -> Result<Expression, Diagnostic> {
+    match &self.current_token.token_type {
       TokenType::ADD => self.parse_expression(Op::Add),
       TokenType::SUB => self.parse_expression(Op::Sub),
       TokenType::MUL => self.parse_expression(Op::Mul),
       TokenType::DIV => self.parse_expression(Op::Div),
       TokenType::MOD => self.parse_expression(Op::Mod),
+      TokenType::SDIV => self.parse_expression(Op::SDiv),
+      TokenType::SMOD => self.parse_expression(Op::SMod),
       TokenType::LT => self.parse_expression(Op::Lt),
       TokenType::LTOE => self.parse_expression(Op::LtOE),
       TokenType::GT => self.parse_expression(Op::Gt),
       TokenType::GTOE => self.parse_expression(Op::GtOE),
+      TokenType::SLT => self.parse_expression(Op::SLt),
+      TokenType::SLTOE => self.parse_expression(Op::SLtOE),
+      TokenType::SGT => self.parse_expression(Op::SGt),
+      TokenType::SGTOE => self.parse_expression(Op::SGtOE),
       TokenType::EQ => self.parse_expression(Op::Eq),
       TokenType::NEQ => self.parse_expression(Op::NotEq),
       TokenType::BAND => self.parse_expression(Op::And),
       TokenType::BOR => self.parse_expression(Op::Or),
       TokenType::BXOR => self.parse_expression(Op::XOr),
       TokenType::BNOT => self.parse_expression(Op::Not),
-      TokenType::INT(i) => Ok(Expression {
-        op: Op::Num(*i),
-        exprs: vec![],
-      }),
+      TokenType::AT => self.parse_expression(Op::MLoad),
+      TokenType::DAT => self.parse_expression(Op::SLoad),
+      TokenType::DEF => self.parse_def(),
+      TokenType::INT(_) => {
+        let num = self.parse_program()?;
+        self.advance_tokens();
+
+        if self.current_token.token_type != TokenType::RPAREN {
+          return Err(self.error("Expected `)`"));
+        }
+
+        Ok(num)
+      }
       TokenType::IDENT(i) => match i.as_ref() {
         "if" => self.parse_expression(Op::If),
         "when" => self.parse_expression(Op::When),
         "unless" => self.parse_expression(Op::Unless),
-        _ => Err(String::from("Error pase_expression")),
+        "while" => self.parse_expression(Op::While),
+        "until" => self.parse_expression(Op::Until),
+        "for" => self.parse_expression(Op::For),
+        "signextend" => self.parse_expression(Op::SignExtend),
+        "asm" => self.parse_asm(),
+        "seq" => self.parse_expression(Op::Seq),
+        "let" => self.parse_let(),
+        "returnlll" => self.parse_expression(Op::ReturnLll),
+        "deploy" => self.parse_expression(Op::Deploy),
+        "dispatch" => self.parse_dispatch(),
+        "abi-encode" => self.parse_abi_encode(),
+        "abi-decode" => self.parse_abi_decode(),
+        "emit" => self.parse_emit(),
+        "return" => self.parse_expression(Op::Return),
+        "require" => self.parse_require(),
+        "and" => self.parse_expression(Op::LogicalAnd),
+        "or" => self.parse_expression(Op::LogicalOr),
+        "not" => self.parse_expression(Op::LogicalNot),
+        _ => {
+          let name = i.clone();
+
+          match builtin(&name) {
+            Some(opcode) => {
+              let open = self.delimiters.last().copied();
+              let mut expr = self.parse_expression(Op::Builtin(opcode))?;
+              expr.span = open.unwrap_or(expr.span).to(self.current_token.span);
+              Self::check_builtin_arity(&name, opcode, expr)
+            }
+            None => self.parse_expression(Op::Ident(name)),
+          }
+        }
       },
-      TokenType::EOF => Ok(Expression::end_program()),
-      _ => Err(String::from("Error pase_expression")),
+      TokenType::RPAREN => Err(
+        Diagnostic::error("E0001", "Empty expression", self.current_token.span)
+          .with_label("expected an operator"),
+      ),
+      _ => Err(self.error("Expected operator")),
     }
   }
 
-  fn parse_expression(&mut self, op: Op) -> Result<Expression, String> {
-    let mut add_expr = Expression { op, exprs: vec![] };
+  fn parse_expression(&mut self, op: Op) -> Result<Expression, Diagnostic> {
+    let mut add_expr = Expression::new(op, vec![], self.current_token.span);
 
     let mut exprs = vec![];
 
@@ -91,8 +198,480 @@ impl<'a> Parser<'a> {
 
     self.advance_tokens();
 
+    if self.current_token.token_type == TokenType::EOF {
+      return Err(self.unclosed());
+    }
+
     add_expr.exprs = exprs;
 
     Ok(add_expr)
   }
+
+  fn check_builtin_arity(
+    name: &str,
+    opcode: Opcode,
+    expr: Expression,
+  ) -> Result<Expression, Diagnostic> {
+    let inputs = opcode.info().map_or(0, |info| info.inputs);
+
+    if expr.exprs.len() == inputs {
+      return Ok(expr);
+    }
+
+    Err(
+      Diagnostic::error(
+        "E0004",
+        format!(
+          "`{}` takes {} argument(s) but {} were supplied",
+          name,
+          inputs,
+          expr.exprs.len()
+        ),
+        expr.span,
+      )
+      .with_label(format!("expected {}", inputs)),
+    )
+  }
+
+  /// Reports a definition or binding of a name that belongs to a builtin.
+  fn check_not_builtin(name: &str, span: Span) -> Result<(), Diagnostic> {
+    match builtin(name) {
+      Some(_) => Err(
+        Diagnostic::error("E0006", format!("`{}` is a builtin", name), span)
+          .with_label("cannot be redefined"),
+      ),
+      None => Ok(()),
+    }
+  }
+
+  /// Parses `{ expr... }` into a sequence.
+  fn parse_block(&mut self) -> Result<Expression, Diagnostic> {
+    let span = self.current_token.span;
+    self.delimiters.push(span);
+
+    let mut exprs = vec![];
+    self.advance_tokens();
+
+    while self.current_token.token_type != TokenType::RBRACE {
+      exprs.push(self.parse_program()?);
+      self.advance_tokens();
+    }
+
+    self.delimiters.pop();
+
+    Ok(Expression::new(Op::Seq, exprs, span.to(self.current_token.span)))
+  }
+
+  /// Parses `[addr] value` into an MSTORE and `[[key]] value` into an SSTORE.
+  fn parse_store(&mut self) -> Result<Expression, Diagnostic> {
+    let span = self.current_token.span;
+
+    let (op, depth) = if self.peek_token.token_type == TokenType::LBRACKET {
+      self.advance_tokens();
+      (Op::SStore, 2)
+    } else {
+      (Op::MStore, 1)
+    };
+
+    self.advance_tokens();
+    let location = self.parse_program()?;
+
+    for _ in 0..depth {
+      self.advance_tokens();
+
+      if self.current_token.token_type != TokenType::RBRACKET {
+        return Err(
+          self
+            .error("Expected `]`")
+            .with_secondary(span, "store opened here"),
+        );
+      }
+    }
+
+    self.advance_tokens();
+
+    if self.current_token.token_type == TokenType::EOF
+      || self.current_token.token_type == TokenType::RPAREN
+    {
+      return Err(
+        Diagnostic::error("E0001", "Expected value to store", self.current_token.span)
+          .with_secondary(span.to(location.span), "store without a value"),
+      );
+    }
+
+    let value = self.parse_program()?;
+    let span = span.to(value.span);
+
+    Ok(Expression::new(op, vec![location, value], span))
+  }
+
+  /// Parses `(let ((name value)...) body)`.
+  fn parse_let(&mut self) -> Result<Expression, Diagnostic> {
+    let let_span = self.current_token.span;
+    self.advance_tokens();
+
+    if self.current_token.token_type != TokenType::LPAREN {
+      return Err(
+        self
+          .error("Expected binding list")
+          .with_note("bindings are written as `(let ((name value)...) body)`"),
+      );
+    }
+    self.delimiters.push(self.current_token.span);
+
+    let mut bindings = vec![];
+    let mut values = vec![];
+
+    loop {
+      self.advance_tokens();
+
+      match self.current_token.token_type {
+        TokenType::RPAREN => break,
+        TokenType::LPAREN => self.delimiters.push(self.current_token.span),
+        _ => return Err(self.error("Expected `(name value)` binding")),
+      }
+
+      self.advance_tokens();
+      let name = match &self.current_token.token_type {
+        TokenType::IDENT(name) => (name.clone(), self.current_token.span),
+        _ => return Err(self.error("Expected variable name")),
+      };
+      Self::check_not_builtin(&name.0, name.1)?;
+
+      self.advance_tokens();
+      if self.current_token.token_type == TokenType::RPAREN {
+        return Err(
+          Diagnostic::error("E0001", "Expected value to bind", self.current_token.span)
+            .with_secondary(name.1, format!("`{}` is never given a value", name.0)),
+        );
+      }
+      values.push(self.parse_program()?);
+
+      self.advance_tokens();
+      if self.current_token.token_type != TokenType::RPAREN {
+        return Err(self.error("Expected `)` after bound value"));
+      }
+      self.delimiters.pop();
+
+      bindings.push(name);
+    }
+    self.delimiters.pop();
+
+    let mut let_expr = self.parse_expression(Op::Let(bindings))?;
+
+    if let_expr.exprs.len() != 1 {
+      return Err(
+        Diagnostic::error(
+          "E0004",
+          format!("Expected one body expression, found {}", let_expr.exprs.len()),
+          let_span.to(self.current_token.span),
+        )
+        .with_note("bindings are written as `(let ((name value)...) body)`"),
+      );
+    }
+
+    values.append(&mut let_expr.exprs);
+    let_expr.exprs = values;
+
+    Ok(let_expr)
+  }
+
+  /// Parses `(dispatch ("signature" handler)... fallback)`. The fallback is
+  /// optional but must come last.
+  fn parse_dispatch(&mut self) -> Result<Expression, Diagnostic> {
+    let span = self.current_token.span;
+    let mut cases = vec![];
+    let mut exprs = vec![];
+    let mut fallback: Option<Span> = None;
+
+    while self.peek_token.token_type != TokenType::RPAREN
+      && self.peek_token.token_type != TokenType::EOF
+    {
+      self.advance_tokens();
+
+      if let Some(fallback) = fallback {
+        return Err(
+          self
+            .error("Expected `)` after the fallback")
+            .with_secondary(fallback, "fallback here")
+            .with_note("the fallback must come after every case"),
+        );
+      }
+
+      let is_case = self.current_token.token_type == TokenType::LPAREN
+        && matches!(self.peek_token.token_type, TokenType::STR(_));
+
+      if !is_case {
+        let expr = self.parse_program()?;
+        fallback = Some(expr.span);
+        exprs.push(expr);
+        continue;
+      }
+
+      let case_span = self.current_token.span;
+      self.delimiters.push(case_span);
+      self.advance_tokens();
+      let signature = self.parse_abi::<Signature>()?;
+
+      self.advance_tokens();
+      if self.current_token.token_type == TokenType::RPAREN {
+        return Err(
+          Diagnostic::error("E0001", "Expected handler", self.current_token.span)
+            .with_secondary(case_span, "case without a handler"),
+        );
+      }
+      exprs.push(self.parse_program()?);
+
+      self.advance_tokens();
+      if self.current_token.token_type != TokenType::RPAREN {
+        return Err(self.error("Expected `)` after handler"));
+      }
+      self.delimiters.pop();
+
+      cases.push((signature, case_span.to(self.current_token.span)));
+    }
+
+    self.advance_tokens();
+
+    if self.current_token.token_type == TokenType::EOF {
+      return Err(self.unclosed());
+    }
+
+    Ok(Expression::new(Op::Dispatch(cases), exprs, span))
+  }
+
+  /// Parses `(abi-encode "types" offset values...)`.
+  fn parse_abi_encode(&mut self) -> Result<Expression, Diagnostic> {
+    self.advance_tokens();
+    let types = match &self.current_token.token_type {
+      TokenType::STR(types) => parse_types(types)
+        .map_err(|message| Diagnostic::error("E0011", message, self.current_token.span))?,
+      _ => return Err(self.error("Expected a list of types, as in \"address,uint256\"")),
+    };
+
+    self.parse_expression(Op::AbiEncode(types))
+  }
+
+  /// Parses `(abi-decode "type" index)`.
+  fn parse_abi_decode(&mut self) -> Result<Expression, Diagnostic> {
+    let span = self.current_token.span;
+    self.advance_tokens();
+    let ty = self.parse_abi::<AbiType>()?;
+
+    self.advance_tokens();
+    let index = match &self.current_token.token_type {
+      TokenType::INT(index) => index.to_usize().ok_or_else(|| {
+        Diagnostic::error("E0003", "Argument index is too large", self.current_token.span)
+      })?,
+      _ => {
+        return Err(
+          self
+            .error("Expected argument index")
+            .with_note("arguments are numbered from 0, as in `(abi-decode \"uint256\" 0)`"),
+        )
+      }
+    };
+
+    self.advance_tokens();
+    if self.current_token.token_type != TokenType::RPAREN {
+      return Err(self.error("Expected `)`"));
+    }
+
+    Ok(Expression::new(Op::AbiDecode(ty, index), vec![], span))
+  }
+
+  /// Parses `(emit "event" values...)`.
+  fn parse_emit(&mut self) -> Result<Expression, Diagnostic> {
+    self.advance_tokens();
+    let event = self.parse_abi::<Event>()?;
+
+    self.parse_expression(Op::Emit(event))
+  }
+
+  /// Parses `(require cond "message")`, where the message is optional.
+  fn parse_require(&mut self) -> Result<Expression, Diagnostic> {
+    let span = self.current_token.span;
+    let mut exprs = vec![];
+    let mut message = None;
+
+    while self.peek_token.token_type != TokenType::RPAREN
+      && self.peek_token.token_type != TokenType::EOF
+    {
+      self.advance_tokens();
+
+      match &self.current_token.token_type {
+        _ if message.is_some() => return Err(self.error("Expected `)` after the message")),
+        TokenType::STR(text) => message = Some(text.clone()),
+        _ => exprs.push(self.parse_program()?),
+      }
+    }
+
+    self.advance_tokens();
+
+    if self.current_token.token_type == TokenType::EOF {
+      return Err(self.unclosed());
+    }
+
+    Ok(Expression::new(Op::Require(message), exprs, span))
+  }
+
+  /// Parses the current token, a string, as an ABI signature or type.
+  fn parse_abi<T: std::str::FromStr<Err = String>>(&self) -> Result<T, Diagnostic> {
+    match &self.current_token.token_type {
+      TokenType::STR(text) => text
+        .parse()
+        .map_err(|message: String| Diagnostic::error("E0011", message, self.current_token.span)),
+      _ => Err(self.error("Expected a quoted signature or type")),
+    }
+  }
+
+  /// Parses `(asm ...)`: opcode mnemonics, `PUSHn value` or `PUSH value`,
+  /// and raw bytes written as a hex string.
+  fn parse_asm(&mut self) -> Result<Expression, Diagnostic> {
+    let span = self.current_token.span;
+    let mut code = vec![];
+
+    while self.peek_token.token_type != TokenType::RPAREN
+      && self.peek_token.token_type != TokenType::EOF
+    {
+      self.advance_tokens();
+      let token_span = self.current_token.span;
+
+      let instruction = match &self.current_token.token_type {
+        TokenType::IDENT(name) if name.eq_ignore_ascii_case("push") => {
+          Instruction::Push(self.parse_push_value(None)?)
+        }
+        TokenType::IDENT(name) => {
+          let opcode = Opcode::from_name(name).ok_or_else(|| {
+            Diagnostic::error("E0008", format!("Unknown opcode `{}`", name), token_span)
+          })?;
+
+          match opcode.immediate_size() {
+            0 => Instruction::Op(opcode),
+            size => {
+              let value = self.parse_push_value(Some(size))?;
+              let mut bytes = vec![opcode.0];
+              bytes.extend_from_slice(&value.to_be_bytes()[32 - size..]);
+              Instruction::Raw(bytes)
+            }
+          }
+        }
+        TokenType::STR(hex) => Instruction::Raw(from_hex(hex).ok_or_else(|| {
+          Diagnostic::error("E0008", "Invalid raw bytes", token_span)
+            .with_note("raw bytes are written as hex, as in \"0x6001\"")
+        })?),
+        TokenType::INT(_) => {
+          return Err(
+            Diagnostic::error("E0008", "Unexpected value in inline assembly", token_span)
+              .with_label("not an operand of a PUSH")
+              .with_note("raw bytes are written as hex strings, as in \"0x6001\""),
+          )
+        }
+        _ => return Err(self.error("Expected opcode")),
+      };
+
+      code.push(instruction);
+    }
+
+    self.advance_tokens();
+
+    if self.current_token.token_type == TokenType::EOF {
+      return Err(self.unclosed());
+    }
+
+    Ok(Expression::new(Op::Asm(code), vec![], span))
+  }
+
+  /// Parses the operand of a PUSH, checking that it fits in `size` bytes if
+  /// the width is given.
+  fn parse_push_value(&mut self, size: Option<usize>) -> Result<U256, Diagnostic> {
+    let push_span = self.current_token.span;
+
+    let value = match &self.peek_token.token_type {
+      TokenType::INT(value) => *value,
+      _ => {
+        return Err(
+          Diagnostic::error("E0008", "PUSH without a value", push_span)
+            .with_label("expected an integer after this"),
+        )
+      }
+    };
+    self.advance_tokens();
+
+    match size {
+      Some(size) if value.to_be_bytes_trimmed().len() > size => Err(
+        Diagnostic::error(
+          "E0008",
+          format!("Value does not fit in PUSH{}", size),
+          push_span.to(self.current_token.span),
+        ),
+      ),
+      _ => Ok(value),
+    }
+  }
+
+  /// Parses `(def 'name body)` or `(def 'name (params...) body)`.
+  fn parse_def(&mut self) -> Result<Expression, Diagnostic> {
+    self.advance_tokens();
+
+    let name = match &self.current_token.token_type {
+      TokenType::STR(s) => {
+        Self::check_not_builtin(s, self.current_token.span)?;
+        s.clone()
+      }
+      _ => {
+        return Err(
+          self
+            .error("Expected definition name")
+            .with_note("definition names are quoted, as in `(def 'name ...)`"),
+        )
+      }
+    };
+
+    let mut def_expr = self.parse_expression(Op::Def(name, vec![]))?;
+
+    match def_expr.exprs.len() {
+      1 => Ok(def_expr),
+      2 => {
+        let params = Self::params_from(&def_expr.exprs[0]).ok_or_else(|| {
+          Diagnostic::error("E0001", "Expected parameter list", def_expr.exprs[0].span)
+            .with_label("parameters must be bare identifiers")
+        })?;
+        let body = def_expr.exprs.remove(1);
+
+        if let Op::Def(name, _) = def_expr.op {
+          def_expr.op = Op::Def(name, params);
+        }
+        def_expr.exprs = vec![body];
+
+        Ok(def_expr)
+      }
+      _ => Err(
+        Diagnostic::error(
+          "E0004",
+          "Invalid number of arguments to def",
+          def_expr.span.to(self.current_token.span),
+        )
+          .with_note("expected `(def 'name body)` or `(def 'name (params...) body)`"),
+      ),
+    }
+  }
+
+  /// A parameter list parses as a call whose head and arguments are all bare
+  /// identifiers, e.g. `(a b c)`.
+  fn params_from(expr: &Expression) -> Option<Vec<String>> {
+    let mut params = match &expr.op {
+      Op::Ident(name) => vec![name.clone()],
+      _ => return None,
+    };
+
+    for param in &expr.exprs {
+      match &param.op {
+        Op::Ident(name) if param.exprs.is_empty() => params.push(name.clone()),
+        _ => return None,
+      }
+    }
+
+    Some(params)
+  }
 }
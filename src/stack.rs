@@ -0,0 +1,210 @@
+use crate::disasm::disassemble;
+use crate::instruction::{Instruction, Label};
+use crate::opcode::Opcode;
+use std::collections::HashMap;
+
+/// Opcodes after which execution never falls through.
+pub const TERMINATORS: [Opcode; 6] = [
+  Opcode::JUMP,
+  Opcode::STOP,
+  Opcode::RETURN,
+  Opcode::REVERT,
+  Opcode::INVALID,
+  Opcode::SELFDESTRUCT,
+];
+
+/// Analysing code whose starting height is unknown starts from here, so
+/// that only consuming more than a full stack counts as an underflow.
+const UNKNOWN_HEIGHT: usize = 1024;
+
+/// Items an instruction pops and pushes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Effect {
+  pub inputs: usize,
+  pub outputs: usize,
+}
+
+/// Why the stack heights of some code could not be worked out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackError {
+  /// The instruction at `index` needs `needed` items but only `height` are
+  /// on the stack.
+  Underflow {
+    index: usize,
+    height: usize,
+    needed: usize,
+  },
+  /// Two paths reach the instruction at `index` with different heights.
+  Mismatch {
+    index: usize,
+    expected: usize,
+    found: usize,
+  },
+  /// The effect of the instruction at `index` cannot be known, as with a
+  /// jump to a computed target.
+  Unknown { index: usize },
+}
+
+impl StackError {
+  pub fn index(&self) -> usize {
+    match self {
+      StackError::Underflow { index, .. }
+      | StackError::Mismatch { index, .. }
+      | StackError::Unknown { index } => *index,
+    }
+  }
+}
+
+impl Instruction {
+  /// The items this instruction pops and pushes, or `None` for inline
+  /// assembly that jumps or halts and for data, which must never run.
+  pub fn stack_effect(&self) -> Option<Effect> {
+    match self {
+      Instruction::Op(opcode) => opcode.info().map(|info| Effect {
+        inputs: info.inputs,
+        outputs: info.outputs,
+      }),
+      Instruction::Push(_) | Instruction::PushLabel(_) => Some(Effect {
+        inputs: 0,
+        outputs: 1,
+      }),
+      Instruction::Label(_) => Some(Effect {
+        inputs: 0,
+        outputs: 0,
+      }),
+      Instruction::Raw(bytes) => raw_effect(bytes),
+      Instruction::Data(..) => None,
+    }
+  }
+}
+
+/// Works out the stack height before each instruction of `code`, and after
+/// the last one, given the height it starts at. Jumps are followed to their
+/// labels. Instructions that can never run, and the end if execution never
+/// falls off it, have no height.
+///
+/// There is no separate check that DUP and SWAP stay within 16 items: DUPn
+/// and SWAPn take `n` and `n + 1` inputs, so reaching past what is on the
+/// stack is an underflow, and nothing deeper can be encoded. Generated code
+/// never needs more, since variables live in memory rather than on the stack.
+pub fn heights(code: &[Instruction], start: usize) -> Result<Vec<Option<usize>>, StackError> {
+  let labels = code
+    .iter()
+    .enumerate()
+    .filter_map(|(i, instruction)| match instruction {
+      Instruction::Label(label) => Some((*label, i)),
+      _ => None,
+    })
+    .collect::<HashMap<Label, usize>>();
+
+  let mut heights = vec![None; code.len() + 1];
+  let mut pending = vec![(0, start)];
+
+  while let Some((mut i, mut height)) = pending.pop() {
+    loop {
+      match heights[i] {
+        Some(expected) if expected == height => break,
+        Some(expected) => {
+          return Err(StackError::Mismatch {
+            index: i,
+            expected,
+            found: height,
+          })
+        }
+        None => heights[i] = Some(height),
+      }
+
+      let instruction = match code.get(i) {
+        Some(instruction) => instruction,
+        None => break,
+      };
+
+      let effect = match instruction.stack_effect() {
+        Some(effect) => effect,
+        // Inline assembly that halts ends the path.
+        None if ends_in_terminator(instruction) => break,
+        None => return Err(StackError::Unknown { index: i }),
+      };
+
+      if height < effect.inputs {
+        return Err(StackError::Underflow {
+          index: i,
+          height,
+          needed: effect.inputs,
+        });
+      }
+      height = height - effect.inputs + effect.outputs;
+
+      let target = match (i.checked_sub(1).map(|j| &code[j]), instruction) {
+        (Some(Instruction::PushLabel(label)), Instruction::Op(_)) => labels.get(label).copied(),
+        _ => None,
+      };
+
+      match instruction {
+        Instruction::Op(Opcode::JUMP) => {
+          pending.push((target.ok_or(StackError::Unknown { index: i })?, height));
+          break;
+        }
+        Instruction::Op(Opcode::JUMPI) => {
+          pending.push((target.ok_or(StackError::Unknown { index: i })?, height));
+        }
+        Instruction::Op(opcode) if TERMINATORS.contains(opcode) => break,
+        _ => {}
+      }
+
+      i += 1;
+    }
+  }
+
+  Ok(heights)
+}
+
+/// The number of items `code` leaves on the stack, relative to the height it
+/// starts at, when execution falls off its end, or `None` if it never does.
+pub fn net_effect(code: &[Instruction]) -> Result<Option<isize>, StackError> {
+  let end = heights(code, UNKNOWN_HEIGHT)?[code.len()];
+  Ok(end.map(|end| end as isize - UNKNOWN_HEIGHT as isize))
+}
+
+/// Whether `instruction` is inline assembly that halts without jumping.
+fn ends_in_terminator(instruction: &Instruction) -> bool {
+  let lines = match instruction {
+    Instruction::Raw(bytes) => disassemble(bytes),
+    _ => return false,
+  };
+
+  let jumps = lines
+    .iter()
+    .any(|line| line.opcode == Opcode::JUMP || line.opcode == Opcode::JUMPI);
+
+  !jumps && lines.iter().any(|line| TERMINATORS.contains(&line.opcode))
+}
+
+/// The combined effect of straight-line inline assembly, or `None` if it
+/// jumps or halts.
+fn raw_effect(bytes: &[u8]) -> Option<Effect> {
+  let mut effect = Effect {
+    inputs: 0,
+    outputs: 0,
+  };
+
+  for line in disassemble(bytes) {
+    if line.opcode == Opcode::JUMPI || TERMINATORS.contains(&line.opcode) {
+      return None;
+    }
+
+    let info = line.opcode.info()?;
+
+    // Items taken from below what the assembly itself pushed count as
+    // inputs of the whole block.
+    if info.inputs > effect.outputs {
+      effect.inputs += info.inputs - effect.outputs;
+      effect.outputs = 0;
+    } else {
+      effect.outputs -= info.inputs;
+    }
+    effect.outputs += info.outputs;
+  }
+
+  Some(effect)
+}
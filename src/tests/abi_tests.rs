@@ -0,0 +1,120 @@
+use crate::abi::{describe, parse_types, AbiType, Entry, Event, Mutability, Param, Signature};
+
+#[test]
+fn selectors() {
+  let selector = |signature: &str| signature.parse::<Signature>().unwrap().selector();
+
+  assert_eq!(selector("transfer(address,uint256)"), [0xa9, 0x05, 0x9c, 0xbb]);
+  assert_eq!(selector("transfer(address to, uint256 amount)"), [0xa9, 0x05, 0x9c, 0xbb]);
+  assert_eq!(selector("balanceOf(address) view returns (uint256)"), [0x70, 0xa0, 0x82, 0x31]);
+  assert_eq!(selector("totalSupply()"), [0x18, 0x16, 0x0d, 0xdd]);
+}
+
+#[test]
+fn signature_parts() {
+  let signature = "transfer(address to, uint)  returns (bool)"
+    .parse::<Signature>()
+    .unwrap();
+
+  assert_eq!(signature.canonical(), "transfer(address,uint256)");
+  assert_eq!(signature.mutability, Mutability::NonPayable);
+  assert_eq!(
+    signature.inputs[0],
+    Param {
+      ty: AbiType::Address,
+      name: "to".to_owned()
+    }
+  );
+  assert_eq!(signature.outputs[0].ty, AbiType::Bool);
+
+  let deposit = "deposit() payable".parse::<Signature>().unwrap();
+  assert_eq!(deposit.mutability, Mutability::Payable);
+  assert!(deposit.outputs.is_empty());
+}
+
+#[test]
+fn invalid_signatures() {
+  for signature in [
+    "transfer",
+    "(address)",
+    "f(uint7)",
+    "f(uint264)",
+    "f(bytes)",
+    "f(string)",
+    "f(uint256[])",
+    "f(address a b)",
+    "f() cheap",
+    "f() returns uint256",
+  ] {
+    assert!(signature.parse::<Signature>().is_err(), "{}", signature);
+  }
+}
+
+#[test]
+fn types() {
+  assert_eq!(
+    parse_types("uint8,int,bytes4,bool"),
+    Ok(vec![
+      AbiType::Uint(8),
+      AbiType::Int(256),
+      AbiType::FixedBytes(4),
+      AbiType::Bool
+    ])
+  );
+  assert_eq!(parse_types(""), Ok(vec![]));
+  assert!(parse_types("uint08").is_err());
+  assert!(parse_types("bytes33").is_err());
+}
+
+#[test]
+fn describes_functions_and_fallback() {
+  let signature = "get(uint8 key) view returns (bool)".parse::<Signature>().unwrap();
+  let abi = describe(&[Entry::Function(signature), Entry::Fallback]);
+
+  assert_eq!(
+    abi.to_string(),
+    concat!(
+      r#"[{"type":"function","name":"get","#,
+      r#""inputs":[{"name":"key","type":"uint8","internalType":"uint8"}],"#,
+      r#""outputs":[{"name":"","type":"bool","internalType":"bool"}],"#,
+      r#""stateMutability":"view"},"#,
+      r#"{"type":"fallback","stateMutability":"nonpayable"}]"#
+    )
+  );
+}
+
+#[test]
+fn events() {
+  let transfer = "Transfer(address indexed from, address indexed to, uint256 value)"
+    .parse::<Event>()
+    .unwrap();
+
+  assert_eq!(transfer.canonical(), "Transfer(address,address,uint256)");
+  assert_eq!(transfer.topic()[..4], [0xdd, 0xf2, 0x52, 0xad]);
+  assert!(transfer.inputs[1].indexed && !transfer.inputs[2].indexed);
+  assert!(!transfer.anonymous);
+
+  assert!("E(uint8 indexed, uint8 indexed, uint8 indexed, uint8 indexed) anonymous"
+    .parse::<Event>()
+    .is_ok());
+  assert!("E(uint8 indexed, uint8 indexed, uint8 indexed, uint8 indexed)"
+    .parse::<Event>()
+    .is_err());
+  assert!("E(uint8 a indexed)".parse::<Event>().is_err());
+  assert!("E() view".parse::<Event>().is_err());
+}
+
+#[test]
+fn describes_events() {
+  let event = "Ping(uint8 indexed id, bool) anonymous".parse::<Event>().unwrap();
+
+  assert_eq!(
+    describe(&[Entry::Event(event)]).to_string(),
+    concat!(
+      r#"[{"type":"event","name":"Ping","inputs":["#,
+      r#"{"name":"id","type":"uint8","internalType":"uint8","indexed":true},"#,
+      r#"{"name":"","type":"bool","internalType":"bool","indexed":false}],"#,
+      r#""anonymous":true}]"#
+    )
+  );
+}
@@ -0,0 +1,83 @@
+use crate::assembler::assemble;
+use crate::instruction::{Instruction, Label};
+use crate::opcode::Opcode;
+use crate::uint::U256;
+
+#[test]
+fn push_widths() {
+  let code = vec![
+    Instruction::Push(U256::from(0u32)),
+    Instruction::Push(U256::from(0xffu32)),
+    Instruction::Push(U256::from(0x1234u32)),
+  ];
+
+  assert_eq!(
+    assemble(&code).byte_code,
+    vec![0x60, 0x00, 0x60, 0xff, 0x61, 0x12, 0x34]
+  );
+}
+
+#[test]
+fn labels_resolve_to_byte_offsets() {
+  let code = vec![
+    Instruction::Push(U256::from(0x1234u32)),
+    Instruction::PushLabel(Label(1)),
+    Instruction::Op(Opcode::JUMP),
+    Instruction::Label(Label(1)),
+  ];
+  let assembly = assemble(&code);
+
+  assert_eq!(
+    assembly.byte_code,
+    vec![0x61, 0x12, 0x34, 0x60, 0x06, 0x56, 0x5b]
+  );
+  assert_eq!(assembly.offsets, vec![0, 3, 5, 6]);
+}
+
+#[test]
+fn literal_cannot_be_mistaken_for_label() {
+  let dest = U256::from_str_radix("64657374", 16).unwrap();
+  let code = vec![Instruction::Push(dest), Instruction::Label(Label(0))];
+
+  assert_eq!(
+    assemble(&code).byte_code,
+    vec![0x63, 0x64, 0x65, 0x73, 0x74, 0x5b]
+  );
+}
+
+#[test]
+fn label_width_grows_until_stable() {
+  // With one-byte references `a` lands at 255 and fits, but `b` does not.
+  // Widening the reference to `b` pushes `a` to 256, so its reference has to
+  // widen on the next pass as well.
+  let (a, b) = (Label(1), Label(2));
+  let mut code = vec![
+    Instruction::PushLabel(a),
+    Instruction::PushLabel(b),
+    Instruction::Op(Opcode::JUMP),
+  ];
+  code.extend((0..125).map(|_| Instruction::Push(U256::from(1u32))));
+  code.push(Instruction::Label(a));
+  code.extend((0..10).map(|_| Instruction::Push(U256::from(1u32))));
+  code.push(Instruction::Label(b));
+
+  let assembly = assemble(&code);
+  let a_offset = assembly.offsets[128];
+  let b_offset = assembly.offsets[139];
+
+  assert_eq!((a_offset, b_offset), (257, 278));
+  assert_eq!(&assembly.byte_code[..6], &[0x61, 0x01, 0x01, 0x61, 0x01, 0x16]);
+  assert_eq!(assembly.byte_code[a_offset], 0x5b);
+  assert_eq!(assembly.byte_code[b_offset], 0x5b);
+}
+
+#[test]
+fn backward_reference_to_start() {
+  let code = vec![
+    Instruction::Label(Label(0)),
+    Instruction::PushLabel(Label(0)),
+    Instruction::Op(Opcode::JUMP),
+  ];
+
+  assert_eq!(assemble(&code).byte_code, vec![0x5b, 0x60, 0x00, 0x56]);
+}
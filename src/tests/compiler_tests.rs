@@ -0,0 +1,454 @@
+use crate::abi::Entry;
+use crate::compiler::Compiler;
+use crate::diagnostic::Diagnostic;
+use crate::evm::Vm;
+use crate::instruction::Instruction;
+use crate::lexer::Lexer;
+use crate::opcode::Opcode;
+use crate::optimizer::OptLevel;
+use crate::output::to_hex;
+use crate::parser::Parser;
+use crate::uint::U256;
+
+fn compile(input: &str) -> Result<String, Diagnostic> {
+  let lexer = Lexer::new(input);
+  let mut parser = Parser::new(lexer);
+  let ast = parser.parse()?;
+  Compiler::new(ast).compile().map(|byte_code| to_hex(&byte_code))
+}
+
+#[test]
+fn basic_add() {
+  assert_eq!(compile("(+ 1 2)").unwrap(), "6002600101");
+}
+
+#[test]
+fn def_constant() {
+  assert_eq!(
+    compile("(def 'two 2) (+ 1 two)").unwrap(),
+    compile("(+ 1 2)").unwrap()
+  );
+}
+
+#[test]
+fn def_substitutes_params() {
+  assert_eq!(
+    compile("(def 'sub2 (a b) (- a b)) (sub2 10 (* 2 3))").unwrap(),
+    compile("(- 10 (* 2 3))").unwrap()
+  );
+}
+
+#[test]
+fn compiling_twice_gives_the_same_code() {
+  let ast = Parser::new(Lexer::new("(def 'a (x) (let ((y x)) y)) (a 1)")).parse().unwrap();
+  let mut compiler = Compiler::new(ast);
+  let first = compiler.compile().unwrap();
+
+  assert_eq!(compiler.compile().unwrap(), first);
+}
+
+#[test]
+fn definitions_are_not_values() {
+  let error = compile("(+ 1 (def 'h 2))").unwrap_err();
+  assert_eq!(error.code, "E0012");
+  assert_eq!(error.primary.span.start, 5);
+
+  assert_eq!(compile("(let ((x (def 'h 2))) x)").unwrap_err().code, "E0012");
+  assert!(compile("{ (def 'a 1) (def 'b 2) } (+ a b)").is_ok());
+  assert!(compile("(def 'defs (def 'c 3)) defs (+ c 1)").is_ok());
+}
+
+#[test]
+fn def_errors() {
+  let code = |input| compile(input).unwrap_err().code;
+
+  assert_eq!(code("(+ 1 undefined)"), "E0005");
+  assert_eq!(code("(def 'a 1) (def 'a 2)"), "E0006");
+  assert_eq!(code("(def 'f (x) x) (f 1 2)"), "E0004");
+  assert_eq!(code("(def 'f (x) (f x)) (f 1)"), "E0007");
+}
+
+#[test]
+fn unused_def_warning() {
+  let ast = Parser::new(Lexer::new("(def 'a 1) (def '_b 2) (def 'c 3) c")).parse().unwrap();
+  let mut compiler = Compiler::new(ast);
+  compiler.compile().unwrap();
+
+  let warnings = compiler.warnings();
+  assert_eq!(warnings.len(), 1);
+  assert_eq!(warnings[0].code, "W0001");
+  assert_eq!(warnings[0].primary.span.col, 1);
+}
+
+#[test]
+fn error_spans() {
+  let error = compile("(+ 1\n  (if 1 2))").unwrap_err();
+
+  assert_eq!(error.code, "E0004");
+  assert_eq!((error.primary.span.row, error.primary.span.col), (2, 3));
+}
+
+#[test]
+fn memory_and_storage() {
+  assert_eq!(compile("(@ 0x20)").unwrap(), "602051");
+  assert_eq!(compile("(@@ 1)").unwrap(), "600154");
+  assert_eq!(compile("[0x20] 5").unwrap(), "6005602052");
+  assert_eq!(compile("[[1]] (@ 0)").unwrap(), "600051600155");
+}
+
+#[test]
+fn signed_operators() {
+  assert_eq!(compile("(S< 1 2)").unwrap(), "6002600112");
+  assert_eq!(compile("(S> 1 2)").unwrap(), "6002600113");
+  assert_eq!(compile("(S<= 1 2)").unwrap(), "600260011315");
+  assert_eq!(compile("(S>= 1 2)").unwrap(), "600260011215");
+  assert_eq!(compile("(S/ 6 3)").unwrap(), "6003600605");
+  assert_eq!(compile("(S% 7 3)").unwrap(), "6003600707");
+  assert_eq!(compile("(signextend 0 0xff)").unwrap(), "60ff60000b");
+}
+
+#[test]
+fn wide_literals() {
+  let max = format!("0x{}", "f".repeat(64));
+  assert_eq!(compile(&max).unwrap(), format!("7f{}", "f".repeat(64)));
+  assert_eq!(compile("0").unwrap(), "6000");
+  assert_eq!(compile("0x0100").unwrap(), "610100");
+  assert!(compile(&format!("(+ 1 {}0)", max)).is_err());
+}
+
+#[test]
+fn source_map_points_at_innermost_expressions() {
+  let ast = Parser::new(Lexer::new("(def 'a 1)\n(+ a 2)\n[0] 3")).parse().unwrap();
+  let mut compiler = Compiler::new(ast);
+  compiler.compile().unwrap();
+
+  let positions = compiler
+    .source_map()
+    .iter()
+    .map(|mapping| (mapping.offset, mapping.length, mapping.span.row, mapping.span.col))
+    .collect::<Vec<_>>();
+
+  // `2`, then `1` from the body of `a`, then the `+` itself.
+  assert_eq!(
+    positions,
+    vec![
+      (0, 2, 2, 6),
+      (2, 2, 1, 9),
+      (4, 1, 2, 1),
+      (5, 2, 3, 5),
+      (7, 2, 3, 2),
+      (9, 1, 3, 1)
+    ]
+  );
+}
+
+#[test]
+fn optimized_code_keeps_source_map() {
+  let ast = Parser::new(Lexer::new("(+ 1 2)\n(* 3 4)")).parse().unwrap();
+  let mut compiler = Compiler::new(ast).with_optimization(OptLevel::O1);
+
+  assert_eq!(to_hex(&compiler.compile().unwrap()), "6003600c");
+  assert_eq!(
+    compiler
+      .source_map()
+      .iter()
+      .map(|mapping| (mapping.offset, mapping.span.row))
+      .collect::<Vec<_>>(),
+    vec![(0, 1), (2, 2)]
+  );
+}
+
+#[test]
+fn generates_instructions() {
+  let ast = Parser::new(Lexer::new("(+ 1 2 3)")).parse().unwrap();
+  let code = Compiler::new(ast).generate().unwrap();
+
+  assert_eq!(
+    code,
+    vec![
+      Instruction::Push(U256::from(3u32)),
+      Instruction::Push(U256::from(2u32)),
+      Instruction::Push(U256::from(1u32)),
+      Instruction::Op(Opcode::ADD),
+      Instruction::Op(Opcode::ADD),
+    ]
+  );
+}
+
+#[test]
+fn if_jumps_to_byte_offsets() {
+  assert_eq!(
+    compile("(if 1 0x0a 0x0b)").unwrap(),
+    "6001600a57600b600d565b600a5b"
+  );
+}
+
+#[test]
+fn large_when_body_uses_wide_jump() {
+  let body = format!("(+ {})", "1 ".repeat(200));
+  let byte_code = Compiler::new(
+    Parser::new(Lexer::new(&format!("(when 1 {})", body)))
+      .parse()
+      .unwrap(),
+  )
+  .compile()
+  .unwrap();
+
+  // PUSH1 1, ISZERO, PUSH2 dest, JUMPI
+  assert_eq!(&byte_code[..4], &[0x60, 0x01, 0x15, 0x61]);
+  let dest = ((byte_code[4] as usize) << 8) | byte_code[5] as usize;
+  assert_eq!(dest, byte_code.len() - 1);
+  assert_eq!(byte_code[dest], 0x5b);
+}
+
+#[test]
+fn inline_assembly_is_emitted_verbatim() {
+  assert_eq!(
+    compile("(asm CALLER PUSH2 0x20 MSTORE \"fe\")").unwrap(),
+    "3361002052fe"
+  );
+  assert_eq!(compile("(+ 1 (asm PUSH 0x0100 SLOAD))").unwrap(), "61010054600101");
+}
+
+#[test]
+fn let_uses_memory_slots() {
+  // 1 -> [0x80], 2 -> [0xa0], then x + y
+  assert_eq!(
+    compile("(let ((x 1) (y 2)) (+ x y))").unwrap(),
+    "6001608052600260a05260a05160805101"
+  );
+}
+
+#[test]
+fn let_errors() {
+  let code = |input| compile(input).unwrap_err().code;
+
+  assert_eq!(code("(let ((x 1)) y)"), "E0005");
+  assert_eq!(code("(+ (let ((x 1)) x) x)"), "E0005");
+  assert_eq!(code("(let ((x 1) (x 2)) x)"), "E0006");
+  assert_eq!(code("(let ((x 1)) (x 2))"), "E0004");
+}
+
+#[test]
+fn let_shadowing_warns() {
+  let ast = Parser::new(Lexer::new("(def 'k 1) (let ((k 2)) (let ((k k)) k))"))
+    .parse()
+    .unwrap();
+  let mut compiler = Compiler::new(ast);
+  compiler.compile().unwrap();
+
+  let warnings = compiler
+    .warnings()
+    .iter()
+    .map(|warning| (warning.code, warning.primary.span.col))
+    .collect::<Vec<_>>();
+  assert_eq!(warnings, vec![("W0001", 1), ("W0002", 19), ("W0002", 32)]);
+}
+
+#[test]
+fn macro_arguments_respect_let_scope() {
+  assert_eq!(
+    compile("(def 'f (x) (let ((x 2)) x)) (f 7)").unwrap(),
+    compile("(let ((x 2)) x)").unwrap()
+  );
+}
+
+#[test]
+fn macro_bindings_do_not_capture_arguments() {
+  let source = "(def 'f (a) (let ((x 5)) (+ x a))) (let ((x 1)) (f x))";
+  let mut compiler = Compiler::new(Parser::new(Lexer::new(source)).parse().unwrap());
+  let byte_code = compiler.compile().unwrap();
+  let execution = Vm::new(&byte_code, &[]).run().unwrap();
+
+  assert_eq!(execution.stack, vec![U256::from(6u32)]);
+  assert!(compiler.warnings().is_empty());
+}
+
+#[test]
+fn macro_bodies_cannot_use_variables() {
+  let source = "(def 'f (x) (+ x y)) (let ((y 5)) (f 1))";
+  let error = compile(source).unwrap_err();
+  assert_eq!(error.code, "E0013");
+  assert_eq!(error.primary.span.start, source.find("y)").unwrap());
+
+  assert_eq!(compile("(let ((x 1)) (def 'g x)) (g)").unwrap_err().code, "E0013");
+  assert_eq!(compile("(def 'f y) (let ((y 1)) f)").unwrap_err().code, "E0013");
+
+  // Names the body binds, and other definitions, are fine.
+  assert!(compile("(def 'g (x) (let ((y x)) y)) (let ((y 5)) (g y))").is_ok());
+  assert!(compile("(def 'a 1) (def 'f (x) (+ x a)) (let ((y 5)) (f y))").is_ok());
+}
+
+#[test]
+fn while_jumps_backwards() {
+  // start: cond ISZERO PUSH end JUMPI body POP PUSH start JUMP end:
+  assert_eq!(
+    compile("(while (@ 0) 7)").unwrap(),
+    "5b60005115600e576007506000565b"
+  );
+  assert_eq!(
+    compile("(until (@ 0) 7)").unwrap(),
+    "5b600051600d576007506000565b"
+  );
+}
+
+#[test]
+fn loop_errors() {
+  let code = |input| compile(input).unwrap_err().code;
+
+  assert_eq!(code("(while 1)"), "E0004");
+  assert_eq!(code("(for 1 2 3)"), "E0004");
+  assert_eq!(code("(while 1 (asm POP))"), "E0009");
+  assert_eq!(code("(while 1 (asm PUSH1 0 JUMP))"), "E0009");
+}
+
+#[test]
+fn sequences_pop_intermediate_values() {
+  assert_eq!(compile("{ 1 [0] 2 3 }").unwrap(), "60015060026000526003");
+  assert_eq!(compile("(seq (+ 1 2) (@ 0))").unwrap(), compile("{ (+ 1 2) (@ 0) }").unwrap());
+  assert_eq!(compile("{}").unwrap(), "");
+}
+
+#[test]
+fn stack_errors() {
+  let code = |input| compile(input).unwrap_err().code;
+
+  assert_eq!(code("(if 1 2 [0] 3)"), "E0009");
+  assert_eq!(code("(asm POP)"), "E0010");
+  assert_eq!(code("1 (asm SWAP2)"), "E0010");
+  assert_eq!(code("1 (asm SWAP1)"), "E0010");
+  assert!(compile("1 2 (asm SWAP1)").is_ok());
+
+  // DUP and SWAP reach at most 16 items deep, and only as deep as the stack.
+  assert_eq!(compile(&format!("{}(asm DUP16)", "1 ".repeat(15))).unwrap_err().code, "E0010");
+  assert_eq!(compile(&format!("{}(asm SWAP16)", "1 ".repeat(16))).unwrap_err().code, "E0010");
+  assert!(compile(&format!("{}(asm DUP16)", "1 ".repeat(16))).is_ok());
+  assert!(compile(&format!("{}(asm SWAP16)", "1 ".repeat(17))).is_ok());
+  assert_eq!(code("1 (asm DUP17)"), "E0008");
+}
+
+#[test]
+fn inline_assembly_cannot_reach_below_the_stack() {
+  // DUPn and SWAPn only go 16 deep, so reaching deeper than the stack is
+  // always an underflow.
+  let error = compile("1 (+ 2 (asm DUP3))").unwrap_err();
+
+  assert_eq!(error.code, "E0010");
+  assert_eq!(
+    error.primary.message,
+    "an instruction here needs 3 value(s) but only 1 are on the stack"
+  );
+  assert!(compile("1 2 3 (+ 4 (asm DUP3))").is_ok());
+}
+
+#[test]
+fn branches_that_halt_fit_any_height() {
+  assert!(compile("(if (@ 0) (asm \"fd\") 5)").is_ok());
+  assert!(compile("(if 1 (asm PUSH1 0 SELFDESTRUCT) 5)").is_ok());
+  assert_eq!(compile("(when 1 5)").unwrap(), compile("(when 1 { 5 })").unwrap());
+  assert!(compile("(when 1 5)").unwrap().contains("600550"));
+}
+
+#[test]
+fn returnlll_copies_runtime_after_init_code() {
+  assert_eq!(
+    compile("(returnlll (+ 1 2))").unwrap(),
+    "600580600b6000396000f3".to_owned() + "6002600101"
+  );
+  assert!(compile("(let ((x 1)) (returnlll x))").is_err());
+
+  let code = |input| compile(input).unwrap_err().code;
+  assert_eq!(code("(returnlll 1 2)"), "E0004");
+  assert_eq!(code("(deploy 1)"), "E0004");
+}
+
+#[test]
+fn dispatch_errors() {
+  let code = |input| compile(input).unwrap_err().code;
+
+  assert_eq!(code("(dispatch (\"f()\" 1) (\"f()\" 2))"), "E0006");
+  assert_eq!(code("(dispatch (\"f() returns (uint256)\" [0] 1))"), "E0009");
+  assert_eq!(code("(dispatch (\"f() returns (uint256, bool)\" 1))"), "E0009");
+  assert_eq!(code("(abi-encode \"uint256,bool\" 0 1)"), "E0004");
+  assert!(compile("(dispatch (\"f() returns (uint256)\" (asm \"fd\")))").is_ok());
+}
+
+#[test]
+fn interface_lists_dispatched_functions_once() {
+  let source = "(def 'ping (emit \"Ping(uint8)\" 1)) \
+                (def 'api (dispatch (\"f()\" ping) (\"g(bool)\" 2) 3)) api api ping";
+  let ast = Parser::new(Lexer::new(source)).parse().unwrap();
+  let mut compiler = Compiler::new(ast);
+  compiler.compile().unwrap();
+
+  let names = compiler
+    .interface()
+    .iter()
+    .map(|entry| match entry {
+      Entry::Function(signature) => signature.canonical(),
+      Entry::Fallback => "fallback".to_owned(),
+      Entry::Event(event) => event.canonical(),
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(names, vec!["f()", "g(bool)", "fallback", "Ping(uint8)"]);
+}
+
+#[test]
+fn builtins_take_first_argument_on_top() {
+  assert_eq!(compile("(calldataload (+ 4 caller))").unwrap(), "3360040135");
+  assert_eq!(compile("(calldatacopy 0 4 32)").unwrap(), "60206004600037");
+}
+
+#[test]
+fn calls_and_creates() {
+  // CALL pops gas, address, value, input offset and size, then output
+  // offset and size.
+  assert_eq!(
+    compile("(call gas 0xaa 5 0 0x24 0x40 0x20)").unwrap(),
+    "6020604060246000600560aa5af1"
+  );
+  assert_eq!(compile("(staticcall gas 0xaa 0 4 0 32)").unwrap(), "602060006004600060aa5afa");
+  assert_eq!(compile("(delegatecall gas 0xaa 0 4 0 32)").unwrap(), "602060006004600060aa5af4");
+  assert_eq!(compile("(create 1 0 10)").unwrap(), "600a60006001f0");
+  assert_eq!(compile("(create2 0 0 10 0x5a17)").unwrap(), "615a17600a60006000f5");
+}
+
+#[test]
+fn logs() {
+  assert_eq!(compile("(log0 0 32)").unwrap(), "60206000a0");
+  assert_eq!(compile("(log2 0 32 1 2)").unwrap(), "6002600160206000a2");
+  assert_eq!(compile("(emit \"E(uint8 indexed a, bool b)\" 1)").unwrap_err().code, "E0004");
+}
+
+#[test]
+fn return_and_require_arity() {
+  assert_eq!(compile("(return)").unwrap_err().code, "E0004");
+  assert_eq!(compile("(return 0 32 1)").unwrap_err().code, "E0004");
+  assert_eq!(compile("(require \"a\")").unwrap_err().code, "E0004");
+  assert_eq!(compile("(return 0 32)").unwrap(), "60206000f3");
+}
+
+#[test]
+fn logical_operators_are_not_bitwise() {
+  assert_eq!(compile("(not caller)").unwrap(), "3315");
+  assert_eq!(compile("(~ caller)").unwrap(), "3319");
+  assert_eq!(compile("(not 1 2)").unwrap_err().code, "E0004");
+  // CALLER ISZERO ISZERO DUP1 ISZERO PUSH1 0x0c JUMPI POP ORIGIN ISZERO ISZERO JUMPDEST
+  assert_eq!(compile("(and caller origin)").unwrap(), "3315158015600c57503215155b");
+}
+
+#[test]
+fn optimizer_leaves_inline_assembly_alone() {
+  let optimized = |input: &str, level| {
+    let ast = Parser::new(Lexer::new(input)).parse().unwrap();
+    to_hex(&Compiler::new(ast).with_optimization(level).compile().unwrap())
+  };
+
+  for level in [OptLevel::O1, OptLevel::O2] {
+    assert_eq!(optimized("(asm PUSH 5 POP)", level), "600550");
+    assert_eq!(
+      optimized("(asm PUSH1 4 JUMP STOP JUMPDEST PUSH1 7)", level),
+      "600456005b6007"
+    );
+  }
+}
@@ -0,0 +1,69 @@
+use crate::diagnostic::{Diagnostic, Span};
+
+#[test]
+fn render_with_caret() {
+  let source = "(+ 1\n  (* 2 foo))\n";
+  let span = Span::new(12, 15, 2, 8);
+  let diagnostic = Diagnostic::error("E0005", "Cannot find `foo` in this scope", span)
+    .with_label("not defined")
+    .with_note("definitions must appear before they are used");
+
+  let expected = "\
+error[E0005]: Cannot find `foo` in this scope
+ --> test.lll:2:8
+  |
+2 |   (* 2 foo))
+  |        ^^^ not defined
+  |
+  = note: definitions must appear before they are used
+";
+
+  assert_eq!(diagnostic.render("test.lll", source), expected);
+}
+
+#[test]
+fn render_secondary_labels() {
+  let source = "(def 'a 1)\n(def 'a 2)";
+  let span = Span::new(11, 21, 2, 1);
+  let diagnostic = Diagnostic::error("E0006", "`a` is defined multiple times", span)
+    .with_secondary(Span::new(0, 10, 1, 1), "previous definition here");
+
+  let rendered = diagnostic.render("test.lll", source);
+
+  assert!(rendered.contains("2 | (def 'a 2)\n  | ^^^^^^^^^^\n"));
+  assert!(rendered.contains("1 | (def 'a 1)\n  | ---------- previous definition here\n"));
+}
+
+#[test]
+fn render_labels_in_source_order() {
+  let source = "(+ 1\n  (- 2\n";
+  let diagnostic = Diagnostic::error("E0002", "unclosed", Span::new(12, 12, 3, 1))
+    .with_secondary(Span::new(7, 8, 2, 3), "unclosed delimiter");
+
+  let expected = "\
+error[E0002]: unclosed
+ --> test.lll:3:1
+  |
+2 |   (- 2
+  |   - unclosed delimiter
+3 | 
+  | ^
+";
+  assert_eq!(diagnostic.render("test.lll", source), expected);
+
+  let diagnostic = Diagnostic::error("E0002", "unclosed", Span::new(4, 4, 1, 5))
+    .with_secondary(Span::new(0, 1, 1, 1), "unclosed delimiter");
+
+  assert!(diagnostic
+    .render("test.lll", "(+ 1")
+    .ends_with("1 | (+ 1\n  | - unclosed delimiter\n  |     ^\n"));
+}
+
+#[test]
+fn span_join() {
+  let a = Span::new(4, 6, 1, 5);
+  let b = Span::new(10, 12, 2, 1);
+
+  assert_eq!(a.to(b), Span::new(4, 12, 1, 5));
+  assert_eq!(b.to(a), Span::new(4, 12, 1, 5));
+}
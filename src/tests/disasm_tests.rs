@@ -0,0 +1,51 @@
+use crate::disasm::{disassemble, render, Line};
+use crate::opcode::Opcode;
+
+#[test]
+fn splits_push_data() {
+  assert_eq!(
+    disassemble(&[0x61, 0x01, 0x02, 0x01]),
+    vec![
+      Line {
+        offset: 0,
+        opcode: Opcode::push(2),
+        data: vec![0x01, 0x02],
+      },
+      Line {
+        offset: 3,
+        opcode: Opcode::ADD,
+        data: vec![],
+      },
+    ]
+  );
+}
+
+#[test]
+fn marks_jump_destinations() {
+  let asm = render(&[0x60, 0x04, 0x56, 0x00, 0x5b]);
+
+  assert_eq!(
+    asm,
+    "  0000  PUSH1 0x04              ; loc_0004\n  \
+     0002  JUMP\n  \
+     0003  STOP\n\
+     loc_0004:\n  \
+     0004  JUMPDEST\n"
+  );
+}
+
+#[test]
+fn unknown_and_truncated() {
+  assert_eq!(
+    render(&[0x0c, 0x62, 0xff]),
+    "  0000  UNKNOWN 0x0c\n  0001  PUSH3 0xff              ; truncated\n"
+  );
+}
+
+#[test]
+fn opcode_names() {
+  assert_eq!(Opcode::from_name("sstore"), Some(Opcode::SSTORE));
+  assert_eq!(Opcode::from_name("SWAP16"), Some(Opcode::SWAP16));
+  assert_eq!(Opcode::from_name("PUSH33"), None);
+  assert_eq!(Opcode(0xf1).info().map(|info| (info.inputs, info.outputs)), Some((7, 1)));
+}
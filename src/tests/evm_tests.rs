@@ -0,0 +1,401 @@
+use crate::abi::Signature;
+use crate::compiler::Compiler;
+use crate::evm::{Context, Execution, Halt, Vm, VmError};
+use crate::lexer::Lexer;
+use crate::optimizer::OptLevel;
+use crate::output::from_hex;
+use crate::parser::Parser;
+use crate::uint::U256;
+
+fn run(input: &str) -> Execution {
+  let ast = Parser::new(Lexer::new(input)).parse().unwrap();
+  let byte_code = Compiler::new(ast).compile().unwrap();
+  Vm::new(&byte_code, &[]).run().unwrap()
+}
+
+fn run_hex(hex: &str, calldata: &[u8]) -> Result<Execution, VmError> {
+  Vm::new(&from_hex(hex).unwrap(), calldata).run()
+}
+
+fn top(input: &str) -> U256 {
+  *run(input).stack.last().unwrap()
+}
+
+fn num(n: u64) -> U256 {
+  U256::from(n)
+}
+
+#[test]
+fn arithmetic() {
+  assert_eq!(top("(+ 1 2 3)"), num(6));
+  assert_eq!(top("(- 10 4)"), num(6));
+  assert_eq!(top("(* 6 7)"), num(42));
+  assert_eq!(top("(/ 7 2)"), num(3));
+  assert_eq!(top("(% 7 2)"), num(1));
+  assert_eq!(top("(/ 7 0)"), num(0));
+  assert_eq!(top("(- 0 1)"), U256::MAX);
+  assert_eq!(
+    top("(+ 0xffffffffffffffff 1)"),
+    U256::from_str_radix("10000000000000000", 16).unwrap()
+  );
+  assert_eq!(
+    top("(* 0xffffffffffffffffffffffff 0xffffffffffffffffffffffff)"),
+    U256::from_str_radix("fffffffffffffffffffffffe000000000000000000000001", 16).unwrap()
+  );
+}
+
+#[test]
+fn signed_arithmetic() {
+  let minus = |n: u64| num(n).wrapping_neg();
+
+  assert_eq!(top("(S/ (- 0 7) 2)"), minus(3));
+  assert_eq!(top("(S% (- 0 7) 2)"), minus(1));
+  assert_eq!(top("(S< (- 0 1) 1)"), num(1));
+  assert_eq!(top("(S> (- 0 1) 1)"), num(0));
+  assert_eq!(top("(signextend 0 0xff)"), U256::MAX);
+  assert_eq!(top("(signextend 0 0x7f)"), num(0x7f));
+}
+
+#[test]
+fn comparisons_and_bits() {
+  assert_eq!(top("(< 1 2)"), num(1));
+  assert_eq!(top("(>= 1 2)"), num(0));
+  assert_eq!(top("(<= 2 2)"), num(1));
+  assert_eq!(top("(S>= (- 0 1) 1)"), num(0));
+  // Each operand is evaluated once.
+  assert_eq!(top("[0] 1 (<= { [0] (+ (@ 0) 1) (@ 0) } 3) (@ 0)"), num(2));
+  assert_eq!(top("(!= 1 2)"), num(1));
+  assert_eq!(top("(& 0x0f 0x3c)"), num(0x0c));
+  assert_eq!(top("(| 0x0f 0x30)"), num(0x3f));
+  assert_eq!(top("(~ 0)"), U256::MAX);
+}
+
+#[test]
+fn extended_opcodes() {
+  let code = [
+    "600a60ff60ff08", // ADDMOD 0xff 0xff 10
+    "600a60ff60ff09", // MULMOD 0xff 0xff 10
+    "600a60020a",     // EXP 2 10
+    "60ff601f1a",     // BYTE 31 0xff
+    "600160041b",     // SHL 4 1
+    "60ff1960041d",   // SAR 4 (NOT 0xff)
+  ]
+  .concat();
+
+  assert_eq!(
+    run_hex(&code, &[]).unwrap().stack,
+    vec![num(0), num(5), num(1024), num(0xff), num(16), U256::MAX.wrapping_sub(num(0x0f))]
+  );
+}
+
+#[test]
+fn memory_and_storage() {
+  assert_eq!(top("[0x20] 5 (@ 0x20)"), num(5));
+
+  let execution = run("[[1]] 7 [[2]] (+ (@@ 1) 1) [[3]] 0");
+  assert_eq!(execution.storage.len(), 2);
+  assert_eq!(execution.storage[&num(1)], num(7));
+  assert_eq!(execution.storage[&num(2)], num(8));
+  assert!(execution.stack.is_empty());
+}
+
+#[test]
+fn branches() {
+  assert_eq!(top("(if (< 1 2) 10 20)"), num(10));
+  assert_eq!(top("(if (> 1 2) 10 20)"), num(20));
+  assert_eq!(run("(when 0 [[0]] 1)").storage.len(), 0);
+  assert_eq!(run("(unless 0 [[0]] 1)").storage.len(), 1);
+}
+
+#[test]
+fn calldata_and_return() {
+  // CALLDATALOAD 0, MSTORE 0, RETURN 0 32
+  let calldata = [0xab; 4];
+  let execution = run_hex("60003560005260206000f3", &calldata).unwrap();
+
+  assert_eq!(execution.halt, Halt::Return);
+  assert_eq!(&execution.return_data[..4], &calldata);
+  assert_eq!(&execution.return_data[4..], &[0; 28]);
+}
+
+#[test]
+fn revert_discards_storage() {
+  // SSTORE 1 1, REVERT 0 0
+  let execution = run_hex("600160015560006000fd", &[]).unwrap();
+
+  assert_eq!(execution.halt, Halt::Revert);
+  assert!(execution.storage.is_empty());
+}
+
+#[test]
+fn errors() {
+  assert_eq!(run_hex("01", &[]), Err(VmError::StackUnderflow { pc: 0 }));
+  assert_eq!(
+    run_hex("600356", &[]),
+    Err(VmError::InvalidJump { pc: 2, target: num(3) })
+  );
+  // The 0x5b inside the push data is not a jump destination.
+  assert_eq!(
+    run_hex("6001565b", &[]).unwrap_err(),
+    VmError::InvalidJump { pc: 2, target: num(1) }
+  );
+  assert_eq!(run_hex("fe", &[]), Err(VmError::InvalidOpcode { pc: 0 }));
+  assert_eq!(run_hex("5b600056", &[]), Err(VmError::StepLimit));
+}
+
+#[test]
+fn let_scoping() {
+  assert_eq!(top("(let ((x 3) (y (* x 2))) (+ x y))"), num(9));
+  assert_eq!(top("(let ((x 1)) (+ (let ((x 10)) x) x))"), num(11));
+  assert_eq!(
+    top("(def 'sq (n) (let ((m n)) (* m m))) (let ((a 4)) (sq (+ a 1)))"),
+    num(25)
+  );
+}
+
+#[test]
+fn loops() {
+  let sum = "[0x00] 0 \
+             (for [0x20] 1 (<= (@ 0x20) 10) [0x20] (+ (@ 0x20) 1) \
+               [0x00] (+ (@ 0x00) (@ 0x20))) \
+             (@ 0x00)";
+  assert_eq!(top(sum), num(55));
+
+  let countdown = run("[[0]] 3 (until (= (@@ 0) 0) [[0]] (- (@@ 0) 1))");
+  assert!(countdown.storage.is_empty());
+  assert!(countdown.stack.is_empty());
+
+  // Values left by the body are popped on every iteration.
+  let execution = run("(for [0x00] 0 (< (@ 0x00) 100) [0x00] (+ (@ 0x00) 1) (@ 0x00))");
+  assert!(execution.stack.is_empty());
+}
+
+#[test]
+fn sequences() {
+  let execution = run("(let ((x { [[1]] 5 (+ (@@ 1) 1) })) { (+ x 1) (* x 2) })");
+
+  assert_eq!(execution.stack, vec![num(12)]);
+  assert_eq!(execution.storage[&num(1)], num(5));
+}
+
+#[test]
+fn deploy_returns_runtime_code() {
+  let deployment = run("(deploy [[0]] 7 (let ((x (@@ 0))) (+ x 1)))");
+  let runtime = "(let ((x (@@ 0))) (+ x 1))";
+  let ast = Parser::new(Lexer::new(runtime)).parse().unwrap();
+
+  assert_eq!(deployment.halt, Halt::Return);
+  assert_eq!(deployment.return_data, Compiler::new(ast).compile().unwrap());
+  assert_eq!(deployment.storage[&num(0)], num(7));
+
+  let called = Vm::new(&deployment.return_data, &[]).run().unwrap();
+  assert_eq!(called.stack, vec![num(1)]);
+}
+
+fn call(signature: &str, args: &[u64]) -> Vec<u8> {
+  let mut calldata = signature.parse::<Signature>().unwrap().selector().to_vec();
+
+  for arg in args {
+    calldata.extend_from_slice(&num(*arg).to_be_bytes());
+  }
+
+  calldata
+}
+
+#[test]
+fn dispatch() {
+  let source = "(dispatch \
+                  (\"set(uint256)\" [[0]] (abi-decode \"uint256\" 0)) \
+                  (\"get() view returns (uint256)\" (@@ 0)) \
+                  (\"add(uint8 a, uint8 b) pure returns (uint256)\" \
+                    (+ (abi-decode \"uint8\" 0) (abi-decode \"uint8\" 1))) \
+                  [[1]] 1)";
+  let ast = Parser::new(Lexer::new(source)).parse().unwrap();
+  let byte_code = Compiler::new(ast).compile().unwrap();
+  let run_with = |calldata: &[u8]| Vm::new(&byte_code, calldata).run().unwrap();
+
+  let set = run_with(&call("set(uint256)", &[42]));
+  assert_eq!(set.halt, Halt::Stop);
+  assert_eq!(set.storage[&num(0)], num(42));
+  assert!(set.stack.is_empty());
+
+  let get = run_with(&call("get()", &[]));
+  assert_eq!(get.halt, Halt::Return);
+  assert_eq!(get.return_data, num(0).to_be_bytes().to_vec());
+
+  // Arguments are cleaned to their type, so 0x1ff is read as 0xff.
+  let add = run_with(&call("add(uint8,uint8)", &[0x1ff, 2]));
+  assert_eq!(add.return_data, num(0x101).to_be_bytes().to_vec());
+
+  for calldata in [call("other()", &[]), vec![0xa9]] {
+    let fallback = run_with(&calldata);
+    assert_eq!(fallback.halt, Halt::Stop);
+    assert_eq!(fallback.storage[&num(1)], num(1));
+  }
+}
+
+#[test]
+fn dispatch_without_fallback_reverts() {
+  let ast = Parser::new(Lexer::new("(dispatch (\"f()\" 1))")).parse().unwrap();
+  let byte_code = Compiler::new(ast).compile().unwrap();
+
+  let halt = |calldata: &[u8]| Vm::new(&byte_code, calldata).run().unwrap().halt;
+  assert_eq!(halt(&call("f()", &[])), Halt::Stop);
+  assert_eq!(halt(&call("g()", &[])), Halt::Revert);
+  assert_eq!(halt(&[]), Halt::Revert);
+}
+
+#[test]
+fn abi_encode() {
+  let encode = "(abi-encode \"uint8,bool,int8\" 0x20 0x1ff 5 0xff)";
+
+  assert_eq!(top(encode), num(96));
+  assert_eq!(top(&format!("{{ {} (@ 0x20) }}", encode)), num(0xff));
+  assert_eq!(top(&format!("{{ {} (@ 0x40) }}", encode)), num(1));
+  assert_eq!(top(&format!("{{ {} (@ 0x60) }}", encode)), U256::MAX);
+}
+
+#[test]
+fn environment() {
+  let context = Context {
+    address: num(0xa),
+    balance: num(100),
+    caller: num(0xc),
+    callvalue: num(5),
+    chainid: num(1),
+    timestamp: num(1_700_000_000),
+    ..Context::default()
+  };
+  let top = |input: &str| {
+    let ast = Parser::new(Lexer::new(input)).parse().unwrap();
+    let byte_code = Compiler::new(ast).compile().unwrap();
+    let execution = Vm::new(&byte_code, &[]).with_context(context.clone()).run().unwrap();
+    *execution.stack.last().unwrap()
+  };
+
+  assert_eq!(top("(+ caller callvalue)"), num(0x11));
+  assert_eq!(top("(balance address)"), num(100));
+  assert_eq!(top("(balance caller)"), num(0));
+  assert_eq!(top("selfbalance"), num(100));
+  assert_eq!(top("(* chainid timestamp)"), num(1_700_000_000));
+  assert_eq!(top("codesize"), num(1));
+}
+
+#[test]
+fn keccak256() {
+  assert_eq!(
+    top("{ [0] 0x616263 (keccak256 29 3) }"),
+    U256::from_str_radix("4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45", 16)
+      .unwrap()
+  );
+}
+
+#[test]
+fn logs() {
+  let execution = run(
+    "{ [0] 7 \
+       (log1 0 32 5) \
+       (emit \"Transfer(address indexed from, address indexed to, uint256 value)\" \
+         0xaa (+ 0xbb 0x10000000000000000000000000000000000000000) 500) \
+       (emit \"Pair(uint8, uint256)\" 0x1ff 2) }",
+  );
+  let words = |data: &[u8]| data.chunks(32).map(U256::from_be_bytes).collect::<Vec<U256>>();
+
+  assert_eq!(execution.logs.len(), 3);
+  assert_eq!(execution.logs[0].topics, vec![num(5)]);
+  assert_eq!(words(&execution.logs[0].data), vec![num(7)]);
+
+  let transfer =
+    U256::from_str_radix("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef", 16)
+      .unwrap();
+  assert_eq!(execution.logs[1].topics, vec![transfer, num(0xaa), num(0xbb)]);
+  assert_eq!(words(&execution.logs[1].data), vec![num(500)]);
+
+  assert_eq!(execution.logs[2].topics.len(), 1);
+  assert_eq!(words(&execution.logs[2].data), vec![num(0xff), num(2)]);
+}
+
+#[test]
+fn emit_evaluates_topics_then_data_in_order() {
+  // Each use of `next` counts up from 1.
+  let execution = run(
+    "(def 'next { [0] (+ (@ 0) 1) (@ 0) }) \
+     (emit \"E(uint256 a, uint256 indexed b, uint256 indexed c, uint256 indexed d)\" \
+       next next next next) \
+     (emit \"F(uint256 indexed a, uint256 indexed b, uint256 indexed c, uint256 indexed d) \
+       anonymous\" next next next next)",
+  );
+
+  assert_eq!(execution.logs[0].topics[1..], [num(1), num(2), num(3)]);
+  assert_eq!(U256::from_be_bytes(&execution.logs[0].data), num(4));
+  assert_eq!(execution.logs[1].topics, vec![num(5), num(6), num(7), num(8)]);
+}
+
+#[test]
+fn halting() {
+  let returned = run("(return (+ 40 2))");
+  assert_eq!(returned.halt, Halt::Return);
+  assert_eq!(U256::from_be_bytes(&returned.return_data), num(42));
+
+  assert_eq!(run("{ [0] 0x1234 (return 30 2) }").return_data, vec![0x12, 0x34]);
+  assert_eq!(run("{ [[0]] 1 (stop) [[0]] 2 }").storage.get(&num(0)), Some(&num(1)));
+
+  let reverted = run("{ [[0]] 1 (revert 0 0) }");
+  assert_eq!(reverted.halt, Halt::Revert);
+  assert!(reverted.storage.is_empty());
+}
+
+#[test]
+fn require() {
+  let passed = run("{ (require (< 1 2) \"Too low\") [[0]] 1 }");
+  assert_eq!(passed.halt, Halt::Stop);
+  assert_eq!(passed.storage.len(), 1);
+
+  let failed = run("{ (require (> 1 2) \"Too low\") [[0]] 1 }");
+  assert_eq!(failed.halt, Halt::Revert);
+  assert_eq!(
+    failed.return_data,
+    from_hex(concat!(
+      "08c379a0",
+      "0000000000000000000000000000000000000000000000000000000000000020",
+      "0000000000000000000000000000000000000000000000000000000000000007",
+      "546f6f206c6f7700000000000000000000000000000000000000000000000000"
+    ))
+    .unwrap()
+  );
+
+  let long = run("(require 0 \"This message takes up more than one word\")");
+  assert_eq!(long.return_data.len(), 4 + 32 * 4);
+
+  let silent = run("(require 0)");
+  assert_eq!(silent.halt, Halt::Revert);
+  assert!(silent.return_data.is_empty());
+}
+
+#[test]
+fn logical_operators() {
+  assert_eq!(top("(and 1 2 3)"), num(1));
+  assert_eq!(top("(and 1 0 3)"), num(0));
+  assert_eq!(top("(or 0 0 7)"), num(1));
+  assert_eq!(top("(or 0 0)"), num(0));
+  assert_eq!(top("(and)"), num(1));
+  assert_eq!(top("(or)"), num(0));
+  assert_eq!(top("(not 5)"), num(0));
+  assert_eq!(top("(not 0)"), num(1));
+  assert_eq!(top("(not (~ 1))"), num(0));
+  assert_eq!(top("(if (and (> 3 2) (not (= 1 2))) 10 20)"), num(10));
+
+  // Evaluation stops as soon as the result is known.
+  assert!(run("(when (and 0 { [[0]] 1 1 }) [[1]] 1)").storage.is_empty());
+  assert!(!run("(when (or 5 { [[0]] 1 1 }) [[1]] 1)").storage.contains_key(&num(0)));
+  assert_eq!(run("(or 0 { [[0]] 1 1 })").storage.get(&num(0)), Some(&num(1)));
+}
+
+#[test]
+fn optimized_inline_assembly_runs() {
+  let ast = Parser::new(Lexer::new("(asm PUSH1 4 JUMP STOP JUMPDEST PUSH1 7)")).parse().unwrap();
+  let byte_code = Compiler::new(ast).with_optimization(OptLevel::O2).compile().unwrap();
+  let execution = Vm::new(&byte_code, &[]).run().unwrap();
+
+  assert_eq!(execution.stack, vec![num(7)]);
+}
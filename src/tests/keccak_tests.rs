@@ -0,0 +1,37 @@
+use crate::keccak::keccak256;
+use crate::output::to_hex;
+
+#[test]
+fn known_hashes() {
+  assert_eq!(
+    to_hex(&keccak256(b"")),
+    "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+  );
+  assert_eq!(
+    to_hex(&keccak256(b"abc")),
+    "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"
+  );
+  assert_eq!(
+    to_hex(&keccak256(b"Transfer(address,address,uint256)")),
+    "ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+  );
+}
+
+#[test]
+fn inputs_spanning_blocks() {
+  let hash = |length| to_hex(&keccak256(&vec![b'a'; length]));
+
+  // One byte short of a full block, exactly one, and one byte over.
+  assert_eq!(
+    hash(135),
+    "34367dc248bbd832f4e3e69dfaac2f92638bd0bbd18f2912ba4ef454919cf446"
+  );
+  assert_eq!(
+    hash(136),
+    "a6c4d403279fe3e0af03729caada8374b5ca54d8065329a3ebcaeb4b60aa386e"
+  );
+  assert_eq!(
+    hash(137),
+    "d869f639c7046b4929fc92a4d988a8b22c55fbadb802c0c66ebcd484f1915f39"
+  );
+}
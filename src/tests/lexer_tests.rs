@@ -1,11 +1,16 @@
 use crate::lexer::Lexer;
 use crate::token::{TokenType, TokenType::*};
+use crate::uint::U256;
+
+fn int(i: u64) -> TokenType {
+  INT(U256::from(i))
+}
 
 fn test(expected: Vec<TokenType>, input: &str) {
   let mut lexer = Lexer::new(input);
 
-  for i in 0..expected.len() {
-    assert_eq!(expected[i], lexer.next().token_type)
+  for token_type in expected {
+    assert_eq!(token_type, lexer.next().token_type)
   }
 }
 
@@ -39,16 +44,23 @@ fn basic_arith() {
   test(expected, input);
 }
 
+#[test]
+fn signed_arith() {
+  let expected = vec![LPAREN, SDIV, int(6), int(3), RPAREN, SMOD, EOF];
+  let input = "(S/ 6 3) S%";
+  test(expected, input);
+}
+
 #[test]
 fn basic_int() {
-  let expected = vec![INT(42), INT(100), INT(0xaa)];
+  let expected = vec![int(42), int(100), int(0xaa)];
   let input = "42 100 0xaa";
   test(expected, input);
 }
 
 #[test]
 fn single_int() {
-  let expected = vec![LPAREN, INT(5), RPAREN];
+  let expected = vec![LPAREN, int(5), RPAREN];
   let input = "(5)";
   test(expected, input);
 }
@@ -83,12 +95,12 @@ fn basic_contract() {
     LPAREN,
     DEF,
     STR(String::from("scratch")),
-    INT(0x00),
+    int(0x00),
     RPAREN,
     LPAREN,
     DEF,
     STR(String::from("identity")),
-    INT(0xac37eebb),
+    int(0xac37eebb),
     RPAREN,
     LPAREN,
     DEF,
@@ -105,12 +117,12 @@ fn basic_contract() {
     DIV,
     LPAREN,
     IDENT(String::from("calldataload")),
-    INT(0x00),
+    int(0x00),
     RPAREN,
     LPAREN,
     IDENT(String::from("exp")),
-    INT(2),
-    INT(224),
+    int(2),
+    int(224),
     RPAREN,
     RPAREN,
     IDENT(String::from("function-hash")),
@@ -130,13 +142,13 @@ fn basic_contract() {
     IDENT(String::from("scratch")),
     LPAREN,
     IDENT(String::from("calldataload")),
-    INT(0x04),
+    int(0x04),
     RPAREN,
     RPAREN,
     LPAREN,
     IDENT(String::from("return")),
     IDENT(String::from("scratch")),
-    INT(32),
+    int(32),
     RPAREN,
     RPAREN,
     RPAREN,
@@ -158,3 +170,70 @@ fn basic_contract() {
 
   test(epxected, input);
 }
+
+#[test]
+fn brackets() {
+  let expected = vec![
+    LBRACKET, int(0x20), RBRACKET, int(1), LBRACKET, LBRACKET, IDENT(String::from("key")),
+    RBRACKET, RBRACKET, LPAREN, DAT, int(1), RPAREN, EOF,
+  ];
+  let input = "[0x20] 1 [[key]] (@@ 1)";
+  test(expected, input);
+}
+
+#[test]
+fn int_literal_forms() {
+  let expected = vec![
+    int(1_000_000),
+    int(0xdead_beef),
+    int(0b1010),
+    int(0o777),
+    INT(U256::from_str_radix(&"f".repeat(64), 16).unwrap()),
+    EOF,
+  ];
+  let input = format!("1_000_000 0xdead_beef 0b1010 0o777 0x{}", "f".repeat(64));
+  test(expected, &input);
+}
+
+#[test]
+fn int_literal_errors() {
+  let overflow = format!("0x1{}", "0".repeat(64));
+  let tokens = vec![overflow.as_str(), "0x", "12a", "0b102", "1_"];
+
+  for input in tokens {
+    match Lexer::new(input).next().token_type {
+      INVALID(_) => {}
+      token_type => panic!("{} lexed as {:?}", input, token_type),
+    }
+  }
+}
+
+#[test]
+fn int_max_decimal() {
+  let max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+  let expected = U256::from_str_radix(&"f".repeat(64), 16).unwrap();
+
+  assert_eq!(Lexer::new(max).next().token_type, INT(expected));
+  assert_eq!(format!("{}", expected), max);
+  assert!(matches!(
+    Lexer::new("115792089237316195423570985008687907853269984665640564039457584007913129639936")
+      .next()
+      .token_type,
+    INVALID(_)
+  ));
+}
+
+#[test]
+fn identifiers_starting_with_s() {
+  let expected = vec![
+    IDENT(String::from("SSTORE")),
+    IDENT(String::from("SWAP1")),
+    SDIV,
+    IDENT(String::from("S_x")),
+    IDENT(String::from("Sfoo-bar")),
+    IDENT(String::from("S!")),
+    EOF,
+  ];
+  let input = "SSTORE SWAP1 S/ S_x Sfoo-bar S!";
+  test(expected, input);
+}
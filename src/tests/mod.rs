@@ -1,5 +1,12 @@
+mod abi_tests;
+mod assembler_tests;
+mod compiler_tests;
+mod diagnostic_tests;
+mod disasm_tests;
+mod evm_tests;
+mod keccak_tests;
 mod lexer_tests;
+mod optimizer_tests;
+mod output_tests;
 mod parser_tests;
-
-pub use self::lexer_tests::*;
-pub use self::parser_tests::*;
\ No newline at end of file
+mod stack_tests;
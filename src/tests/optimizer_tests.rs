@@ -0,0 +1,110 @@
+use crate::compiler::Compiler;
+use crate::evm::Vm;
+use crate::instruction::{Instruction, Label};
+use crate::lexer::Lexer;
+use crate::opcode::Opcode;
+use crate::optimizer::{optimize, OptLevel};
+use crate::output::to_hex;
+use crate::parser::Parser;
+use crate::uint::U256;
+
+fn compile(input: &str, level: OptLevel) -> Vec<u8> {
+  let ast = Parser::new(Lexer::new(input)).parse().unwrap();
+  Compiler::new(ast).with_optimization(level).compile().unwrap()
+}
+
+fn hex(input: &str, level: OptLevel) -> String {
+  to_hex(&compile(input, level))
+}
+
+#[test]
+fn o0_is_unchanged() {
+  assert_eq!(hex("(+ 1 2 3)", OptLevel::O0), "6003600260010101");
+}
+
+#[test]
+fn folds_constants() {
+  assert_eq!(hex("(+ 1 2 3)", OptLevel::O1), "6006");
+  assert_eq!(hex("(def 'a 6) (- a 2)", OptLevel::O1), "6004");
+  // Folding never makes the code larger.
+  assert_eq!(hex("(- 0 1)", OptLevel::O1), "6001600003");
+  assert_eq!(hex("(- 0 1)", OptLevel::O2), "6001600003");
+  assert_eq!(hex("(~ 0)", OptLevel::O1), "600019");
+  assert_eq!(hex("(S< (- 0 1) 0)", OptLevel::O1), "6001");
+  assert_eq!(hex("(/ 1 0)", OptLevel::O1), "6000");
+  assert_eq!(hex("[[1]] (* 2 3)", OptLevel::O1), "6006600155");
+}
+
+#[test]
+fn peephole_rules() {
+  use Instruction::{Op, Push};
+
+  // Some value the optimizer knows nothing about.
+  let code = |rest: &[Instruction]| [&[Op(Opcode::CALLDATASIZE)], rest].concat();
+
+  let optimized = |code| optimize(code, OptLevel::O1);
+
+  assert_eq!(optimized(code(&[Push(U256::ZERO), Op(Opcode::ADD)])), code(&[]));
+  assert_eq!(optimized(code(&[Push(U256::ONE), Op(Opcode::MUL)])), code(&[]));
+  assert_eq!(optimized(code(&[Op(Opcode::NOT), Op(Opcode::NOT)])), code(&[]));
+  assert_eq!(
+    optimized(code(&[Op(Opcode::ISZERO), Op(Opcode::ISZERO), Op(Opcode::ISZERO)])),
+    code(&[Op(Opcode::ISZERO)])
+  );
+
+  // Only a boolean or a jump condition survives double negation.
+  assert_eq!(
+    optimized(code(&[Op(Opcode::ISZERO), Op(Opcode::ISZERO)])),
+    code(&[Op(Opcode::ISZERO), Op(Opcode::ISZERO)])
+  );
+  assert_eq!(
+    optimized(code(&[
+      Op(Opcode::ISZERO),
+      Op(Opcode::ISZERO),
+      Instruction::PushLabel(Label(1)),
+      Op(Opcode::JUMPI),
+      Instruction::Label(Label(1)),
+    ])),
+    code(&[
+      Instruction::PushLabel(Label(1)),
+      Op(Opcode::JUMPI),
+      Instruction::Label(Label(1)),
+    ])
+  );
+}
+
+#[test]
+fn removes_dead_branches() {
+  assert_eq!(hex("(if (< 1 2) [[1]] 5 [[1]] 6)", OptLevel::O2), "6005600155");
+  assert_eq!(hex("(if 0 10 20)", OptLevel::O2), "6014");
+  assert_eq!(hex("(when (= 0 1) [[1]] 1)", OptLevel::O2), "");
+  assert_eq!(hex("(unless (= 0 1) [[1]] 1)", OptLevel::O2), "6001600155");
+  // O1 folds the condition but keeps the jump.
+  assert_eq!(hex("(if 0 10 20)", OptLevel::O1), "6000600a576014600d565b600a5b");
+}
+
+#[test]
+fn removes_code_after_selfdestruct() {
+  use Instruction::{Op, Push};
+
+  let code = vec![Op(Opcode::CALLER), Op(Opcode::SELFDESTRUCT), Push(U256::ONE)];
+  assert_eq!(optimize(code.clone(), OptLevel::O2), code[..2]);
+}
+
+#[test]
+fn optimized_code_behaves_the_same() {
+  let program = "(def 'k 3) [[1]] (* k (+ 2 5)) \
+                 [[2]] (if (> (@@ 1) 20) (- (@@ 1) 1) 0) \
+                 (when (S< (- 0 1) k) [[3]] (~ (~ 7)))";
+
+  let results = [OptLevel::O0, OptLevel::O1, OptLevel::O2].map(|level| {
+    let byte_code = compile(program, level);
+    (byte_code.len(), Vm::new(&byte_code, &[]).run().unwrap())
+  });
+
+  assert_eq!(results[0].1, results[1].1);
+  assert_eq!(results[0].1, results[2].1);
+  assert!(results[1].0 < results[0].0);
+  assert!(results[2].0 < results[1].0);
+  assert_eq!(results[2].1.storage.len(), 3);
+}
@@ -0,0 +1,79 @@
+use crate::compiler::SourceMapping;
+use crate::diagnostic::Span;
+use crate::json::Json;
+use crate::output::{from_hex, read_bytecode, render, solidity_source_map, Format};
+
+#[test]
+fn text_formats() {
+  let byte_code = [0x60, 0x01, 0x5b];
+
+  assert_eq!(render(Format::Hex, &byte_code, &[], "a.lll"), b"60015b\n");
+  assert_eq!(render(Format::PrefixedHex, &byte_code, &[], "a.lll"), b"0x60015b\n");
+  assert_eq!(render(Format::Bin, &byte_code, &[], "a.lll"), byte_code);
+}
+
+#[test]
+fn json_artifact() {
+  let mapping = SourceMapping {
+    offset: 0,
+    length: 2,
+    span: Span::new(0, 3, 1, 1),
+  };
+  let rendered = render(Format::Json, &[0x60, 0x01], &[mapping], "a.lll");
+  let rendered = String::from_utf8(rendered).unwrap();
+
+  assert!(rendered.contains("\"bytecode\": \"0x6001\""));
+  assert!(rendered.contains("\"source\": \"a.lll\""));
+  assert!(rendered.contains("\"length\": 2"));
+}
+
+#[test]
+fn format_names() {
+  for name in Format::NAMES.iter() {
+    assert!(name.parse::<Format>().is_ok());
+  }
+  assert!("elf".parse::<Format>().is_err());
+}
+
+#[test]
+fn json_escaping() {
+  let json = Json::object(vec![
+    ("quote\"d", Json::str("line\nbreak")),
+    ("list", Json::Array(vec![Json::Num(1), Json::Array(vec![])])),
+  ]);
+
+  assert_eq!(
+    json.to_string(),
+    r#"{"quote\"d":"line\nbreak","list":[1,[]]}"#
+  );
+}
+
+#[test]
+fn reads_hex_or_binary() {
+  assert_eq!(from_hex("0x6001\n"), Some(vec![0x60, 0x01]));
+  assert_eq!(from_hex("600"), None);
+  assert_eq!(from_hex("+1"), None);
+  assert_eq!(read_bytecode(b"6001"), vec![0x60, 0x01]);
+  assert_eq!(read_bytecode(&[0x60, 0x01]), vec![0x60, 0x01]);
+}
+
+#[test]
+fn solidity_source_map_compresses_repeated_fields() {
+  let mapping = |offset, length, start, end| SourceMapping {
+    offset,
+    length,
+    span: Span::new(start, end, 1, start as u32 + 1),
+  };
+  // PUSH1 1, PUSH1 2, ADD, then a STOP no mapping covers.
+  let byte_code = [0x60, 0x01, 0x60, 0x02, 0x01, 0x00];
+  let source_map = [mapping(0, 2, 5, 6), mapping(2, 2, 3, 4), mapping(4, 1, 0, 7)];
+
+  assert_eq!(
+    solidity_source_map(&byte_code, &source_map),
+    "5:1:0:-;3;0:7;-1:-1:-1"
+  );
+  assert_eq!(
+    render(Format::SourceMap, &byte_code, &source_map, "a.lll"),
+    b"5:1:0:-;3;0:7;-1:-1:-1\n"
+  );
+}
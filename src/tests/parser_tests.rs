@@ -1,4 +1,253 @@
+use crate::ast::{Expression, Op};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Lexer;
+use crate::opcode::Opcode;
+use crate::parser::Parser;
+use crate::uint::U256;
+
+fn parse(input: &str) -> Result<Expression, Diagnostic> {
+  let lexer = Lexer::new(input);
+  let mut parser = Parser::new(lexer);
+  parser.parse()
+}
+
 #[test]
 fn basic_num() {
-  
-}
\ No newline at end of file
+  let ast = parse("(5) 6").unwrap();
+
+  assert_eq!(ast.exprs.len(), 2);
+  assert!(matches!(ast.exprs[0].op, Op::Num(n) if n == U256::from(5u32)));
+  assert!(matches!(ast.exprs[1].op, Op::Num(n) if n == U256::from(6u32)));
+}
+
+#[test]
+fn bare_ident_is_not_a_call() {
+  let ast = parse("(+ x 1)").unwrap();
+  let add = &ast.exprs[0];
+
+  assert_eq!(add.exprs.len(), 2);
+  assert!(matches!(&add.exprs[0].op, Op::Ident(name) if name == "x"));
+  assert!(add.exprs[0].exprs.is_empty());
+}
+
+#[test]
+fn def_without_params() {
+  let ast = parse("(def 'scratch 0x00)").unwrap();
+
+  match &ast.exprs[0].op {
+    Op::Def(name, params) => {
+      assert_eq!(name, "scratch");
+      assert!(params.is_empty());
+    }
+    op => panic!("expected def, found {:?}", op),
+  }
+  assert!(matches!(ast.exprs[0].exprs[0].op, Op::Num(n) if n == U256::from(0u32)));
+}
+
+#[test]
+fn def_with_params() {
+  let ast = parse("(def 'add3 (a b c) (+ a b c))").unwrap();
+
+  match &ast.exprs[0].op {
+    Op::Def(name, params) => {
+      assert_eq!(name, "add3");
+      assert_eq!(params, &vec!["a", "b", "c"]);
+    }
+    op => panic!("expected def, found {:?}", op),
+  }
+  assert!(matches!(ast.exprs[0].exprs[0].op, Op::Add));
+}
+
+#[test]
+fn def_requires_quoted_name() {
+  assert!(parse("(def foo 1)").is_err());
+  assert!(parse("(def 'foo (a 1) a)").is_err());
+}
+
+#[test]
+fn store_forms() {
+  let ast = parse("[0x20] (+ 1 2) [[(@ 0)]] 3").unwrap();
+
+  assert_eq!(ast.exprs.len(), 2);
+  assert!(matches!(ast.exprs[0].op, Op::MStore));
+  assert!(matches!(ast.exprs[0].exprs[1].op, Op::Add));
+  assert!(matches!(ast.exprs[1].op, Op::SStore));
+  assert!(matches!(ast.exprs[1].exprs[0].op, Op::MLoad));
+  assert!(parse("[0x20 1").is_err());
+  assert!(parse("[[1] 1").is_err());
+}
+
+#[test]
+fn expression_spans() {
+  let ast = parse("(+ 1\n  (* 2 3))").unwrap();
+  let add = &ast.exprs[0];
+  let mul = &add.exprs[1];
+
+  assert_eq!((add.span.start, add.span.end), (0, 15));
+  assert_eq!((mul.span.row, mul.span.col), (2, 3));
+  assert_eq!((mul.span.start, mul.span.end), (7, 14));
+}
+
+#[test]
+fn delimiter_errors() {
+  let unclosed = parse("(+ 1\n  (* 2 3)").unwrap_err();
+  assert_eq!(unclosed.code, "E0002");
+  assert_eq!(unclosed.secondary[0].span.start, 0);
+
+  let unexpected = parse("(+ 1 2))").unwrap_err();
+  assert_eq!(unexpected.code, "E0002");
+  assert_eq!(unexpected.primary.span.col, 8);
+
+  assert_eq!(parse("(+ 1 0x)").unwrap_err().code, "E0003");
+}
+
+#[test]
+fn inline_assembly() {
+  let ast = parse("(asm PUSH1 0x20 mstore PUSH 0x0100 \"0x6001\")").unwrap();
+
+  assert!(matches!(&ast.exprs[0].op, Op::Asm(code) if code.len() == 4));
+}
+
+#[test]
+fn inline_assembly_errors() {
+  let code = |input| parse(input).unwrap_err().code;
+
+  assert_eq!(code("(asm FOO)"), "E0008");
+  assert_eq!(code("(asm PUSH1)"), "E0008");
+  assert_eq!(code("(asm PUSH1 0x0100)"), "E0008");
+  assert_eq!(code("(asm 0x60)"), "E0008");
+  assert_eq!(code("(asm \"6g\")"), "E0008");
+  assert_eq!(code("(asm ADD"), "E0002");
+}
+
+#[test]
+fn let_bindings() {
+  let ast = parse("(let ((x 1) (y (+ x 1))) (* x y))").unwrap();
+  let let_expr = &ast.exprs[0];
+
+  match &let_expr.op {
+    Op::Let(bindings) => {
+      let names = bindings.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+      assert_eq!(names, vec!["x", "y"]);
+    }
+    op => panic!("expected a let, found {:?}", op),
+  }
+  assert_eq!(let_expr.exprs.len(), 3);
+  assert!(matches!(let_expr.exprs[2].op, Op::Mul));
+}
+
+#[test]
+fn let_errors() {
+  let code = |input| parse(input).unwrap_err().code;
+
+  assert_eq!(code("(let (x 1) x)"), "E0001");
+  assert_eq!(code("(let ((1 2)) 3)"), "E0001");
+  assert_eq!(code("(let ((x)) x)"), "E0001");
+  assert_eq!(code("(let ((x 1 2)) x)"), "E0001");
+  assert_eq!(code("(let ((x 1)))"), "E0004");
+  assert_eq!(code("(let ((x 1)) x"), "E0002");
+}
+
+#[test]
+fn sequences() {
+  let ast = parse("{ 1 [0] 2 (+ 3 4) } (seq 5 6) {}").unwrap();
+
+  assert!(matches!(ast.exprs[0].op, Op::Seq));
+  assert_eq!(ast.exprs[0].exprs.len(), 3);
+  assert_eq!(ast.exprs[0].span.end, 19);
+  assert!(matches!(ast.exprs[1].op, Op::Seq));
+  assert_eq!(ast.exprs[1].exprs.len(), 2);
+  assert!(ast.exprs[2].exprs.is_empty());
+}
+
+#[test]
+fn sequence_delimiter_errors() {
+  let error = parse("{ 1 2").unwrap_err();
+  assert_eq!(error.code, "E0002");
+  assert_eq!(error.secondary[0].span.start, 0);
+
+  let error = parse("(+ 1 }").unwrap_err();
+  assert_eq!((error.code, error.message.as_str()), ("E0002", "Mismatched closing delimiter"));
+
+  assert_eq!(parse("}").unwrap_err().code, "E0002");
+}
+
+#[test]
+fn dispatch_cases() {
+  let ast = parse("(dispatch (\"f(uint256)\" 1) (\"g()\" 2) (+ 3 4))").unwrap();
+  let dispatch = &ast.exprs[0];
+
+  match &dispatch.op {
+    Op::Dispatch(cases) => {
+      let names = cases
+        .iter()
+        .map(|(signature, _)| signature.canonical())
+        .collect::<Vec<_>>();
+      assert_eq!(names, vec!["f(uint256)", "g()"]);
+    }
+    op => panic!("expected a dispatch, found {:?}", op),
+  }
+  assert_eq!(dispatch.exprs.len(), 3);
+}
+
+#[test]
+fn abi_errors() {
+  let code = |input| parse(input).unwrap_err().code;
+
+  assert_eq!(code("(dispatch (\"f(string)\" 1))"), "E0011");
+  assert_eq!(code("(dispatch (\"f()\"))"), "E0001");
+  assert_eq!(code("(dispatch 1 (\"f()\" 2))"), "E0001");
+  assert_eq!(code("(abi-decode \"uint256\" x)"), "E0001");
+  assert_eq!(code("(abi-decode \"uint256\" 0 1)"), "E0001");
+  assert_eq!(code("(abi-decode \"bytes\" 0)"), "E0011");
+  assert_eq!(code("(abi-encode \"uint9\" 0 1)"), "E0011");
+  assert_eq!(code("(emit \"E(uint256 a b)\" 1)"), "E0011");
+  assert_eq!(code("(emit E 1)"), "E0001");
+}
+
+#[test]
+fn builtins() {
+  let ast = parse("caller (calldataload 4) (+ timestamp 1)").unwrap();
+
+  assert!(matches!(ast.exprs[0].op, Op::Builtin(Opcode::CALLER)));
+  assert!(matches!(ast.exprs[1].op, Op::Builtin(Opcode::CALLDATALOAD)));
+  assert_eq!(ast.exprs[1].exprs.len(), 1);
+  assert!(matches!(ast.exprs[2].exprs[0].op, Op::Builtin(Opcode::TIMESTAMP)));
+  assert!(matches!(parse("CALLER").unwrap().exprs[0].op, Op::Ident(_)));
+}
+
+#[test]
+fn builtin_errors() {
+  let code = |input| parse(input).unwrap_err().code;
+
+  assert_eq!(code("(balance)"), "E0004");
+  assert_eq!(code("(+ balance 1)"), "E0004");
+  assert_eq!(code("(caller 1)"), "E0004");
+  assert_eq!(code("(def 'caller 1)"), "E0006");
+  assert_eq!(code("(let ((gas 1)) gas)"), "E0006");
+
+  let error = parse("(calldataload 1 2)").unwrap_err();
+  assert_eq!((error.primary.span.start, error.primary.span.end), (0, 18));
+}
+
+#[test]
+fn call_arity() {
+  let code = |input| parse(input).unwrap_err().code;
+
+  assert_eq!(code("(call gas 0xaa 0 0 0 0)"), "E0004");
+  assert_eq!(code("(staticcall gas 0xaa 0 0 0 0 0)"), "E0004");
+  assert_eq!(code("(create 0 0)"), "E0004");
+  assert_eq!(code("(create2 0 0 0)"), "E0004");
+  assert!(parse("(delegatecall gas 0xaa 0 0 0 0)").is_ok());
+}
+
+#[test]
+fn halting_errors() {
+  let code = |input| parse(input).unwrap_err().code;
+
+  assert_eq!(code("(stop 1)"), "E0004");
+  assert_eq!(code("(revert 0)"), "E0004");
+  assert_eq!(code("(require 1 \"a\" 2)"), "E0001");
+  assert_eq!(code("(require 1 \"a\" \"b\")"), "E0001");
+  assert!(matches!(parse("(require 1 \"a\")").unwrap().exprs[0].op, Op::Require(Some(_))));
+}
@@ -0,0 +1,88 @@
+use crate::instruction::{Instruction, Label};
+use crate::opcode::Opcode;
+use crate::stack::{heights, net_effect, Effect, StackError};
+use crate::uint::U256;
+
+fn push(n: u64) -> Instruction {
+  Instruction::Push(U256::from(n))
+}
+
+fn op(opcode: Opcode) -> Instruction {
+  Instruction::Op(opcode)
+}
+
+#[test]
+fn straight_line() {
+  assert_eq!(net_effect(&[push(1), push(2), op(Opcode::ADD)]), Ok(Some(1)));
+  assert_eq!(net_effect(&[push(1), push(2), op(Opcode::SSTORE)]), Ok(Some(0)));
+  assert_eq!(net_effect(&[op(Opcode::POP)]), Ok(Some(-1)));
+  assert_eq!(net_effect(&[]), Ok(Some(0)));
+}
+
+#[test]
+fn follows_jumps() {
+  let (then, next) = (Label(1), Label(2));
+  let branch = |else_value: Vec<Instruction>| {
+    [
+      vec![push(1), Instruction::PushLabel(then), op(Opcode::JUMPI)],
+      else_value,
+      vec![
+        Instruction::PushLabel(next),
+        op(Opcode::JUMP),
+        Instruction::Label(then),
+        push(3),
+        Instruction::Label(next),
+      ],
+    ]
+    .concat()
+  };
+
+  assert_eq!(net_effect(&branch(vec![push(2)])), Ok(Some(1)));
+  assert_eq!(
+    net_effect(&branch(vec![])),
+    Err(StackError::Mismatch {
+      index: 7,
+      expected: 1024,
+      found: 1025
+    })
+  );
+}
+
+#[test]
+fn heights_before_each_instruction() {
+  let code = [push(1), push(2), op(Opcode::ADD), op(Opcode::STOP), push(3)];
+
+  assert_eq!(
+    heights(&code, 0),
+    Ok(vec![Some(0), Some(1), Some(2), Some(1), None, None])
+  );
+}
+
+#[test]
+fn underflow() {
+  assert_eq!(
+    heights(&[push(1), op(Opcode::SWAP1)], 0),
+    Err(StackError::Underflow {
+      index: 1,
+      height: 1,
+      needed: 2
+    })
+  );
+  assert!(heights(&[push(1), op(Opcode::SWAP1)], 1).is_ok());
+}
+
+#[test]
+fn inline_assembly() {
+  let raw = |bytes: &[u8]| Instruction::Raw(bytes.to_vec());
+
+  // PUSH1 1, DUP1, then ADD and SWAP1 reaching below the block.
+  assert_eq!(
+    raw(&[0x60, 0x01, 0x80, 0x01, 0x01, 0x90]).stack_effect(),
+    Some(Effect {
+      inputs: 2,
+      outputs: 2
+    })
+  );
+  assert_eq!(net_effect(&[raw(&[0x00]), push(1)]), Ok(None));
+  assert_eq!(net_effect(&[raw(&[0x56])]), Err(StackError::Unknown { index: 0 }));
+}
@@ -1,9 +1,12 @@
+use crate::diagnostic::Span;
+use crate::uint::U256;
 use std::fmt;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, Clone, Debug)]
 pub enum TokenType {
   // TYPES
-  INT(u32),
+  INT(U256),
   STR(String),
   IDENT(String),
   // SYMBOLS
@@ -19,6 +22,8 @@ pub enum TokenType {
   MUL,
   DIV,
   MOD,
+  SDIV,
+  SMOD,
   BAND,
   BOR,
   BXOR,
@@ -37,23 +42,18 @@ pub enum TokenType {
   DAT,
   // KEY WORDS
   DEF,
-  INVALID,
+  INVALID(String),
 }
 
 #[derive(Clone, Debug)]
 pub struct Token {
   pub token_type: TokenType,
-  pub row: u32,
-  pub col: u32,
+  pub span: Span,
 }
 
 impl Token {
-  pub fn new(token_type: TokenType, row: u32, col: u32) -> Self {
-    Token {
-      token_type,
-      row,
-      col,
-    }
+  pub fn new(token_type: TokenType, span: Span) -> Self {
+    Token { token_type, span }
   }
 }
 
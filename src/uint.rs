@@ -0,0 +1,416 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr};
+
+/// An unsigned 256-bit integer, the native word size of the EVM.
+///
+/// Limbs are stored least significant first.
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Default)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+  pub const ZERO: U256 = U256([0; 4]);
+  pub const ONE: U256 = U256([1, 0, 0, 0]);
+  pub const MAX: U256 = U256([u64::MAX; 4]);
+
+  /// Parses a string of digits in the given radix, returning `None` if the
+  /// value does not fit in 256 bits or a digit is out of range.
+  pub fn from_str_radix(digits: &str, radix: u32) -> Option<Self> {
+    let mut value = U256::ZERO;
+
+    for c in digits.chars() {
+      let digit = c.to_digit(radix)?;
+      value = value.checked_mul_small(radix as u64)?;
+      value = value.checked_add_small(digit as u64)?;
+    }
+
+    Some(value)
+  }
+
+  pub fn is_zero(self) -> bool {
+    self == U256::ZERO
+  }
+
+  /// Reads a big-endian value of up to 32 bytes.
+  pub fn from_be_bytes(bytes: &[u8]) -> Self {
+    assert!(bytes.len() <= 32, "{} bytes do not fit in a U256", bytes.len());
+
+    let mut padded = [0; 32];
+    padded[32 - bytes.len()..].copy_from_slice(bytes);
+
+    let mut limbs = [0; 4];
+    for (i, limb) in limbs.iter_mut().enumerate() {
+      let start = 24 - i * 8;
+      let mut chunk = [0; 8];
+      chunk.copy_from_slice(&padded[start..start + 8]);
+      *limb = u64::from_be_bytes(chunk);
+    }
+
+    U256(limbs)
+  }
+
+  /// The value as a `usize`, or `None` if it does not fit.
+  pub fn to_usize(self) -> Option<usize> {
+    if self.0[1..].iter().any(|limb| *limb != 0) {
+      return None;
+    }
+
+    usize::try_from(self.0[0]).ok()
+  }
+
+  /// The least significant 64 bits.
+  pub fn low_u64(self) -> u64 {
+    self.0[0]
+  }
+
+  pub fn bit(self, index: usize) -> bool {
+    index < 256 && (self.0[index / 64] >> (index % 64)) & 1 == 1
+  }
+
+  /// Whether the value is negative when read as two's complement.
+  pub fn is_negative(self) -> bool {
+    self.bit(255)
+  }
+
+  pub fn overflowing_add(self, rhs: Self) -> (Self, bool) {
+    let mut result = [0; 4];
+    let mut carry = false;
+
+    for (i, limb) in result.iter_mut().enumerate() {
+      let (sum, overflow_a) = self.0[i].overflowing_add(rhs.0[i]);
+      let (sum, overflow_b) = sum.overflowing_add(carry as u64);
+      *limb = sum;
+      carry = overflow_a || overflow_b;
+    }
+
+    (U256(result), carry)
+  }
+
+  pub fn overflowing_sub(self, rhs: Self) -> (Self, bool) {
+    let mut result = [0; 4];
+    let mut borrow = false;
+
+    for (i, limb) in result.iter_mut().enumerate() {
+      let (diff, overflow_a) = self.0[i].overflowing_sub(rhs.0[i]);
+      let (diff, overflow_b) = diff.overflowing_sub(borrow as u64);
+      *limb = diff;
+      borrow = overflow_a || overflow_b;
+    }
+
+    (U256(result), borrow)
+  }
+
+  pub fn wrapping_add(self, rhs: Self) -> Self {
+    self.overflowing_add(rhs).0
+  }
+
+  pub fn wrapping_sub(self, rhs: Self) -> Self {
+    self.overflowing_sub(rhs).0
+  }
+
+  pub fn wrapping_mul(self, rhs: Self) -> Self {
+    let mut result = [0u64; 4];
+
+    for i in 0..4 {
+      let mut carry = 0u128;
+
+      for j in 0..(4 - i) {
+        let product = (self.0[i] as u128) * (rhs.0[j] as u128) + result[i + j] as u128 + carry;
+        result[i + j] = product as u64;
+        carry = product >> 64;
+      }
+    }
+
+    U256(result)
+  }
+
+  /// Two's complement negation.
+  pub fn wrapping_neg(self) -> Self {
+    (!self).wrapping_add(U256::ONE)
+  }
+
+  pub fn wrapping_pow(self, exponent: Self) -> Self {
+    let mut result = U256::ONE;
+    let mut base = self;
+
+    for i in 0..256 {
+      if exponent.bit(i) {
+        result = result.wrapping_mul(base);
+      }
+      base = base.wrapping_mul(base);
+    }
+
+    result
+  }
+
+  /// Quotient and remainder, or `None` when dividing by zero.
+  pub fn div_rem(self, rhs: Self) -> Option<(Self, Self)> {
+    if rhs.is_zero() {
+      return None;
+    }
+
+    let mut quotient = U256::ZERO;
+    let mut remainder = U256::ZERO;
+
+    for i in (0..256).rev() {
+      let carry = remainder.is_negative();
+      remainder = remainder << 1;
+      if self.bit(i) {
+        remainder.0[0] |= 1;
+      }
+
+      if carry || remainder >= rhs {
+        remainder = remainder.wrapping_sub(rhs);
+        quotient.0[i / 64] |= 1 << (i % 64);
+      }
+    }
+
+    Some((quotient, remainder))
+  }
+
+  /// `(self + rhs) % modulus` computed without intermediate overflow.
+  pub fn add_mod(self, rhs: Self, modulus: Self) -> Option<Self> {
+    let a = self.div_rem(modulus)?.1;
+    let b = rhs.div_rem(modulus)?.1;
+    let (sum, overflow) = a.overflowing_add(b);
+
+    if overflow || sum >= modulus {
+      Some(sum.wrapping_sub(modulus))
+    } else {
+      Some(sum)
+    }
+  }
+
+  /// `(self * rhs) % modulus` computed without intermediate overflow.
+  pub fn mul_mod(self, rhs: Self, modulus: Self) -> Option<Self> {
+    let a = self.div_rem(modulus)?.1;
+    let mut result = U256::ZERO;
+
+    for i in (0..256).rev() {
+      result = result.add_mod(result, modulus)?;
+      if rhs.bit(i) {
+        result = result.add_mod(a, modulus)?;
+      }
+    }
+
+    Some(result)
+  }
+
+  /// The big-endian representation of the value.
+  pub fn to_be_bytes(self) -> [u8; 32] {
+    let mut bytes = [0; 32];
+
+    for (i, limb) in self.0.iter().rev().enumerate() {
+      bytes[i * 8..(i + 1) * 8].copy_from_slice(&limb.to_be_bytes());
+    }
+
+    bytes
+  }
+
+  /// The big-endian representation with leading zero bytes removed. Zero is
+  /// represented by a single zero byte.
+  pub fn to_be_bytes_trimmed(self) -> Vec<u8> {
+    let bytes = self.to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(31);
+    bytes[start..].to_vec()
+  }
+
+  fn checked_mul_small(self, rhs: u64) -> Option<Self> {
+    let mut result = [0; 4];
+    let mut carry = 0u128;
+
+    for (i, limb) in self.0.iter().enumerate() {
+      let product = (*limb as u128) * (rhs as u128) + carry;
+      result[i] = product as u64;
+      carry = product >> 64;
+    }
+
+    if carry == 0 {
+      Some(U256(result))
+    } else {
+      None
+    }
+  }
+
+  fn checked_add_small(self, rhs: u64) -> Option<Self> {
+    let mut result = self.0;
+    let mut carry = rhs;
+
+    for limb in result.iter_mut() {
+      if carry == 0 {
+        break;
+      }
+      let (sum, overflow) = limb.overflowing_add(carry);
+      *limb = sum;
+      carry = overflow as u64;
+    }
+
+    if carry == 0 {
+      Some(U256(result))
+    } else {
+      None
+    }
+  }
+
+  fn div_rem_small(self, rhs: u64) -> (Self, u64) {
+    let mut result = [0; 4];
+    let mut rem = 0u128;
+
+    for i in (0..4).rev() {
+      let dividend = (rem << 64) | self.0[i] as u128;
+      result[i] = (dividend / rhs as u128) as u64;
+      rem = dividend % rhs as u128;
+    }
+
+    (U256(result), rem as u64)
+  }
+}
+
+impl From<u64> for U256 {
+  fn from(value: u64) -> Self {
+    U256([value, 0, 0, 0])
+  }
+}
+
+impl From<u32> for U256 {
+  fn from(value: u32) -> Self {
+    U256::from(value as u64)
+  }
+}
+
+impl From<usize> for U256 {
+  fn from(value: usize) -> Self {
+    U256::from(value as u64)
+  }
+}
+
+impl From<bool> for U256 {
+  fn from(value: bool) -> Self {
+    U256::from(value as u64)
+  }
+}
+
+impl Ord for U256 {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self.0.iter().rev().cmp(other.0.iter().rev())
+  }
+}
+
+impl PartialOrd for U256 {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl BitAnd for U256 {
+  type Output = U256;
+
+  fn bitand(self, rhs: Self) -> Self {
+    U256([0, 1, 2, 3].map(|i| self.0[i] & rhs.0[i]))
+  }
+}
+
+impl BitOr for U256 {
+  type Output = U256;
+
+  fn bitor(self, rhs: Self) -> Self {
+    U256([0, 1, 2, 3].map(|i| self.0[i] | rhs.0[i]))
+  }
+}
+
+impl BitXor for U256 {
+  type Output = U256;
+
+  fn bitxor(self, rhs: Self) -> Self {
+    U256([0, 1, 2, 3].map(|i| self.0[i] ^ rhs.0[i]))
+  }
+}
+
+impl Not for U256 {
+  type Output = U256;
+
+  fn not(self) -> Self {
+    U256(self.0.map(|limb| !limb))
+  }
+}
+
+impl Shl<usize> for U256 {
+  type Output = U256;
+
+  fn shl(self, shift: usize) -> Self {
+    if shift >= 256 {
+      return U256::ZERO;
+    }
+
+    let (limbs, bits) = (shift / 64, shift % 64);
+    let mut result = [0; 4];
+
+    for (i, limb) in result.iter_mut().enumerate().skip(limbs) {
+      *limb = self.0[i - limbs] << bits;
+      if bits > 0 && i > limbs {
+        *limb |= self.0[i - limbs - 1] >> (64 - bits);
+      }
+    }
+
+    U256(result)
+  }
+}
+
+impl Shr<usize> for U256 {
+  type Output = U256;
+
+  fn shr(self, shift: usize) -> Self {
+    if shift >= 256 {
+      return U256::ZERO;
+    }
+
+    let (limbs, bits) = (shift / 64, shift % 64);
+    let mut result = [0; 4];
+
+    for (i, limb) in result.iter_mut().take(4 - limbs).enumerate() {
+      *limb = self.0[i + limbs] >> bits;
+      if bits > 0 && i + limbs + 1 < 4 {
+        *limb |= self.0[i + limbs + 1] << (64 - bits);
+      }
+    }
+
+    U256(result)
+  }
+}
+
+impl fmt::Display for U256 {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.is_zero() {
+      return write!(f, "0");
+    }
+
+    let mut digits = Vec::new();
+    let mut value = *self;
+
+    while !value.is_zero() {
+      let (quotient, rem) = value.div_rem_small(10);
+      digits.push(std::char::from_digit(rem as u32, 10).unwrap());
+      value = quotient;
+    }
+
+    write!(f, "{}", digits.iter().rev().collect::<String>())
+  }
+}
+
+impl fmt::Debug for U256 {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self)
+  }
+}
+
+impl fmt::LowerHex for U256 {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let bytes = self.to_be_bytes_trimmed();
+    let hex = bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+
+    if f.alternate() {
+      write!(f, "0x")?;
+    }
+    write!(f, "{}", hex)
+  }
+}